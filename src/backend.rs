@@ -0,0 +1,786 @@
+use std::collections::HashMap;
+use std::env;
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::gnome;
+use crate::sway;
+#[cfg(feature = "wayland")]
+use crate::wlr_output;
+use crate::x11;
+
+pub enum Backend {
+    Sway,
+    Hyprland,
+    Cosmic,
+    Gnome,
+    KwinWayland,
+    Niri,
+    Wlroots,
+    #[cfg(feature = "wayland")]
+    WlrOutputManagement,
+    Xorg,
+}
+
+impl Backend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Sway => "sway",
+            Backend::Hyprland => "hyprland",
+            Backend::Cosmic => "cosmic",
+            Backend::Gnome => "gnome",
+            Backend::KwinWayland => "kde",
+            Backend::Niri => "niri",
+            Backend::Wlroots => "wlroots",
+            #[cfg(feature = "wayland")]
+            Backend::WlrOutputManagement => "wlr-output-management",
+            Backend::Xorg => "xorg",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct HyprlandMonitor {
+    name: String,
+    transform: u32,
+}
+
+#[derive(Deserialize)]
+struct HyprlandDevices {
+    #[serde(default)]
+    keyboards: Vec<HyprlandKeyboard>,
+}
+
+#[derive(Deserialize)]
+struct HyprlandKeyboard {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct KscreenOutput {
+    name: String,
+    rotation: u8,
+}
+
+#[derive(Deserialize)]
+struct KscreenConfig {
+    outputs: Vec<KscreenOutput>,
+}
+
+#[derive(Deserialize)]
+struct NiriLogicalOutput {
+    transform: String,
+}
+
+#[derive(Deserialize)]
+struct NiriOutput {
+    logical: Option<NiriLogicalOutput>,
+}
+
+#[derive(Deserialize)]
+struct WlrRandrOutput {
+    name: String,
+    transform: String,
+}
+
+/// Maps a numeric wl_output transform to the names sway uses.
+fn wl_transform_name(transform: u32) -> &'static str {
+    match transform {
+        0 => "normal",
+        1 => "90",
+        2 => "180",
+        3 => "270",
+        4 => "flipped",
+        5 => "flipped-90",
+        6 => "flipped-180",
+        _ => "flipped-270",
+    }
+}
+
+pub struct CosmicOutput {
+    pub transform: String,
+    pub width: String,
+    pub height: String,
+}
+
+/// Finds `display` in the output of `cosmic-randr list`, which looks like
+///
+/// ```text
+/// eDP-1 (enabled)
+///   Transform: normal
+///   Modes:
+///     1920x1080@60.000 Hz (current) (preferred)
+/// ```
+pub fn get_cosmic_output(display: &str) -> Result<CosmicOutput, String> {
+    let raw_outputs = command_output(Command::new("cosmic-randr").arg("list"))?;
+
+    let mut in_display = false;
+    let mut transform = None;
+    let mut mode = None;
+    for line in raw_outputs.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            in_display = line.split_whitespace().next() == Some(display);
+            continue;
+        }
+        if !in_display {
+            continue;
+        }
+
+        let line = line.trim();
+        if let Some(value) = line.strip_prefix("Transform:") {
+            transform = Some(value.trim().to_owned());
+        } else if line.contains("(current)") {
+            mode = line
+                .split('@')
+                .next()
+                .and_then(|resolution| resolution.split_once('x'))
+                .map(|(width, height)| (width.to_owned(), height.to_owned()));
+        }
+    }
+
+    match (transform, mode) {
+        (Some(transform), Some((width, height))) => Ok(CosmicOutput {
+            transform,
+            width,
+            height,
+        }),
+        _ => Err(format!(
+            "Unable to determine rotation state: display {} not found in 'cosmic-randr list'",
+            display
+        )),
+    }
+}
+
+/// Whether a process called `name` is running, or false when `pidof` can't
+/// be run to tell.
+pub fn process_running(name: &str) -> bool {
+    match Command::new("pidof").arg(name).output() {
+        Ok(output) => !output.stdout.is_empty(),
+        Err(e) => {
+            debug!("Unable to run pidof: {}", e);
+            false
+        }
+    }
+}
+
+/// The backend used for wlroots compositors without a dedicated backend.
+#[cfg(feature = "wayland")]
+fn generic_wlroots_backend() -> Backend {
+    Backend::WlrOutputManagement
+}
+
+#[cfg(not(feature = "wayland"))]
+fn generic_wlroots_backend() -> Backend {
+    Backend::Wlroots
+}
+
+fn env_set(name: &str) -> bool {
+    env::var_os(name).is_some_and(|value| !value.is_empty())
+}
+
+/// Picks a backend from the session environment: the variables `env_set`
+/// says are set and `$XDG_CURRENT_DESKTOP`, asking whether sway and a real X
+/// server answer only where that is ambiguous. Also returns why.
+fn backend_from_env(
+    env_set: impl Fn(&str) -> bool,
+    desktop: &str,
+    sway_answers: impl FnOnce() -> bool,
+    x_server_running: impl FnOnce() -> bool,
+) -> Option<(Backend, &'static str)> {
+    if env_set("HYPRLAND_INSTANCE_SIGNATURE") {
+        Some((Backend::Hyprland, "$HYPRLAND_INSTANCE_SIGNATURE is set"))
+    } else if env_set("NIRI_SOCKET") {
+        Some((Backend::Niri, "$NIRI_SOCKET is set"))
+    } else if (env_set("SWAYSOCK") || env_set("WAYLAND_DISPLAY")) && sway_answers() {
+        Some((Backend::Sway, "sway answers on its IPC socket"))
+    } else if desktop.contains("COSMIC") {
+        Some((Backend::Cosmic, "$XDG_CURRENT_DESKTOP is COSMIC"))
+    } else if desktop.contains("GNOME") {
+        Some((Backend::Gnome, "$XDG_CURRENT_DESKTOP is GNOME"))
+    } else if desktop.contains("KDE") && env_set("WAYLAND_DISPLAY") {
+        // Plasma on X11 is left to the Xorg backend
+        Some((
+            Backend::KwinWayland,
+            "$XDG_CURRENT_DESKTOP is KDE and $WAYLAND_DISPLAY is set",
+        ))
+    } else if env_set("WAYLAND_DISPLAY") {
+        Some((
+            generic_wlroots_backend(),
+            "$WAYLAND_DISPLAY is set, but not for any compositor with its own backend",
+        ))
+    } else if env_set("DISPLAY") && x_server_running() {
+        Some((Backend::Xorg, "$DISPLAY is an X server other than Xwayland"))
+    } else {
+        None
+    }
+}
+
+/// Picks a backend from the window server processes `running` says are
+/// running, Wayland compositors first since their Xwayland may run alongside.
+/// Also returns why.
+fn backend_from_processes(running: impl Fn(&str) -> bool) -> Option<(Backend, &'static str)> {
+    if running("sway") {
+        Some((Backend::Sway, "a sway process is running"))
+    } else if running("Hyprland") {
+        Some((Backend::Hyprland, "a Hyprland process is running"))
+    } else if running("cosmic-comp") {
+        Some((Backend::Cosmic, "a cosmic-comp process is running"))
+    } else if running("niri") {
+        Some((Backend::Niri, "a niri process is running"))
+    } else if running("gnome-shell") {
+        Some((Backend::Gnome, "a gnome-shell process is running"))
+    } else if running("plasmashell") && running("kwin_wayland") {
+        Some((
+            Backend::KwinWayland,
+            "plasmashell and kwin_wayland processes are running",
+        ))
+    } else if running("Xwayland") {
+        // Some Wayland compositor without a backend of its own
+        Some((
+            generic_wlroots_backend(),
+            "an Xwayland process is running, but no compositor with its own backend",
+        ))
+    } else if running("Xorg") || running("X") {
+        Some((Backend::Xorg, "an Xorg process is running"))
+    } else {
+        None
+    }
+}
+
+fn detect_backend() -> Result<Backend, String> {
+    let desktop = env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+    let (backend, reason) = backend_from_env(
+        env_set,
+        &desktop,
+        || sway::connect().is_ok(),
+        x11::server_running,
+    )
+    .or_else(|| backend_from_processes(process_running))
+    .ok_or_else(|| {
+        "Unable to detect the backend: checked $HYPRLAND_INSTANCE_SIGNATURE, $NIRI_SOCKET, \
+        $SWAYSOCK, $WAYLAND_DISPLAY, $XDG_CURRENT_DESKTOP and $DISPLAY, and found no sway, \
+        Hyprland, cosmic-comp, niri, gnome-shell, plasmashell with kwin_wayland, Xwayland or Xorg \
+        process"
+            .to_owned()
+    })?;
+    info!("Using the {} backend, since {}", backend.name(), reason);
+    Ok(backend)
+}
+
+/// Parses a `--backend` value, detecting the backend for `auto`.
+pub fn parse_backend(name: &str) -> Result<Backend, String> {
+    match name {
+        "auto" => detect_backend(),
+        "sway" => Ok(Backend::Sway),
+        "hyprland" => Ok(Backend::Hyprland),
+        "cosmic" => Ok(Backend::Cosmic),
+        "niri" => Ok(Backend::Niri),
+        "gnome" => Ok(Backend::Gnome),
+        "kde" => Ok(Backend::KwinWayland),
+        "wlroots" | "wlr-randr" => Ok(Backend::Wlroots),
+        #[cfg(feature = "wayland")]
+        "wlr-output-management" => Ok(Backend::WlrOutputManagement),
+        "xorg" | "x11" => Ok(Backend::Xorg),
+        _ => Err(format!("Unknown backend '{}'", name)),
+    }
+}
+
+pub fn command_available(name: &str) -> bool {
+    match env::var_os("PATH") {
+        Some(paths) => env::split_paths(&paths).any(|dir| dir.join(name).is_file()),
+        None => false,
+    }
+}
+
+/// Checks that the commands and session variables `backend` relies on are
+/// there, so a forced backend fails early instead of on the first rotation.
+pub fn check_backend_tools(backend: &Backend) -> Result<(), String> {
+    let (commands, variables): (&[&str], &[&str]) = match backend {
+        Backend::Sway => {
+            return sway::connect()
+                .map_err(|e| format!("The sway backend needs a running sway: {}", e));
+        }
+        Backend::Hyprland => (&["hyprctl"], &[]),
+        Backend::Cosmic => (&["cosmic-randr"], &[]),
+        Backend::Gnome => (&[], &["DBUS_SESSION_BUS_ADDRESS"]),
+        Backend::KwinWayland => (&["kscreen-doctor"], &[]),
+        Backend::Niri => (&["niri"], &[]),
+        Backend::Wlroots => (&["wlr-randr"], &[]),
+        #[cfg(feature = "wayland")]
+        Backend::WlrOutputManagement => (&[], &["WAYLAND_DISPLAY"]),
+        Backend::Xorg => (&[], &["DISPLAY"]),
+    };
+
+    for variable in variables {
+        if !env_set(variable) {
+            return Err(format!(
+                "The {} backend needs ${} to be set",
+                backend.name(),
+                variable
+            ));
+        }
+    }
+    for command in commands {
+        if !command_available(command) {
+            return Err(format!(
+                "The {} backend needs {} to be installed and on $PATH",
+                backend.name(),
+                command
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the names of the displays `backend` has, to pass as `--display`.
+pub fn get_displays(backend: &Backend) -> Result<Vec<String>, String> {
+    match backend {
+        Backend::Sway => Ok(sway::get_outputs()?
+            .into_iter()
+            .map(|output| output.name)
+            .collect()),
+        Backend::Hyprland => {
+            let raw_monitors = command_output(Command::new("hyprctl").arg("-j").arg("monitors"))?;
+            let deserialized: Vec<HyprlandMonitor> = serde_json::from_str(&raw_monitors)
+                .map_err(|e| format!("Unable to deserialize hyprctl JSON output: {}", e))?;
+            Ok(deserialized
+                .into_iter()
+                .map(|monitor| monitor.name)
+                .collect())
+        }
+        Backend::Cosmic => {
+            let raw_outputs = command_output(Command::new("cosmic-randr").arg("list"))?;
+            Ok(raw_outputs
+                .lines()
+                .filter(|line| !line.starts_with(char::is_whitespace))
+                .filter_map(|line| line.split_whitespace().next())
+                .map(String::from)
+                .collect())
+        }
+        Backend::Gnome => gnome::get_displays(),
+        Backend::KwinWayland => {
+            let raw_outputs = command_output(Command::new("kscreen-doctor").arg("-j"))?;
+            let deserialized: KscreenConfig = serde_json::from_str(&raw_outputs)
+                .map_err(|e| format!("Unable to deserialize kscreen-doctor JSON output: {}", e))?;
+            Ok(deserialized
+                .outputs
+                .into_iter()
+                .map(|output| output.name)
+                .collect())
+        }
+        Backend::Niri => {
+            let raw_outputs =
+                command_output(Command::new("niri").arg("msg").arg("--json").arg("outputs"))?;
+            let deserialized: HashMap<String, NiriOutput> = serde_json::from_str(&raw_outputs)
+                .map_err(|e| format!("Unable to deserialize niri JSON output: {}", e))?;
+            let mut displays: Vec<String> = deserialized.into_keys().collect();
+            displays.sort();
+            Ok(displays)
+        }
+        Backend::Wlroots => {
+            let raw_outputs = command_output(Command::new("wlr-randr").arg("--json"))?;
+            let deserialized: Vec<WlrRandrOutput> = serde_json::from_str(&raw_outputs)
+                .map_err(|e| format!("Unable to deserialize wlr-randr JSON output: {}", e))?;
+            Ok(deserialized.into_iter().map(|output| output.name).collect())
+        }
+        #[cfg(feature = "wayland")]
+        Backend::WlrOutputManagement => wlr_output::get_displays(),
+        Backend::Xorg => x11::get_outputs(),
+    }
+}
+
+/// Lists the touchscreens to map to the rotated display, for backends that
+/// don't follow the display by themselves.
+pub fn get_touchscreens(backend: &Backend) -> Result<Vec<String>, String> {
+    match backend {
+        Backend::Sway => Ok(sway::get_inputs()?
+            .into_iter()
+            .filter(|input| input.input_type == "touch")
+            .map(|input| input.identifier)
+            .collect()),
+        Backend::Hyprland
+        | Backend::Cosmic
+        | Backend::Gnome
+        | Backend::KwinWayland
+        | Backend::Niri
+        | Backend::Wlroots
+        | Backend::Xorg => Ok(vec![]),
+        #[cfg(feature = "wayland")]
+        Backend::WlrOutputManagement => Ok(vec![]),
+    }
+}
+
+/// The names of the keyboards Hyprland knows of right now.
+fn get_hyprland_keyboards() -> Result<Vec<String>, String> {
+    let raw_devices = command_output(Command::new("hyprctl").arg("-j").arg("devices"))?;
+    let deserialized: HyprlandDevices = serde_json::from_str(&raw_devices)
+        .map_err(|e| format!("Unable to deserialize hyprctl JSON output: {}", e))?;
+    Ok(deserialized
+        .keyboards
+        .into_iter()
+        .map(|keyboard| keyboard.name)
+        .collect())
+}
+
+pub fn get_keyboards(backend: &Backend) -> Result<Vec<String>, String> {
+    match backend {
+        Backend::Sway => Ok(sway::get_inputs()?
+            .into_iter()
+            .filter(|input| input.input_type == "keyboard")
+            .map(|input| input.identifier)
+            .collect()),
+        Backend::Hyprland => get_hyprland_keyboards(),
+        Backend::Cosmic
+        | Backend::Gnome
+        | Backend::KwinWayland
+        | Backend::Niri
+        | Backend::Wlroots
+        | Backend::Xorg => Ok(vec![]),
+        #[cfg(feature = "wayland")]
+        Backend::WlrOutputManagement => Ok(vec![]),
+    }
+}
+
+/// The app_ids of the fullscreen windows on screen, on the backends that can
+/// tell.
+pub fn get_fullscreen_apps(backend: &Backend) -> Result<Vec<String>, String> {
+    match backend {
+        Backend::Sway => sway::get_fullscreen_apps(),
+        Backend::Hyprland
+        | Backend::Cosmic
+        | Backend::Gnome
+        | Backend::KwinWayland
+        | Backend::Niri
+        | Backend::Wlroots
+        | Backend::Xorg => Ok(vec![]),
+        #[cfg(feature = "wayland")]
+        Backend::WlrOutputManagement => Ok(vec![]),
+    }
+}
+
+pub fn keyboards_attached(backend: &Backend, keyboards: &[String]) -> bool {
+    match backend {
+        Backend::Sway => match sway::get_inputs() {
+            Ok(inputs) => inputs
+                .iter()
+                .any(|input| keyboards.contains(&input.identifier)),
+            Err(e) => {
+                warn!("{}", e);
+                false
+            }
+        },
+        Backend::Hyprland => match get_hyprland_keyboards() {
+            Ok(attached) => attached.iter().any(|keyboard| keyboards.contains(keyboard)),
+            Err(e) => {
+                warn!("{}", e);
+                false
+            }
+        },
+        Backend::Cosmic
+        | Backend::Gnome
+        | Backend::KwinWayland
+        | Backend::Niri
+        | Backend::Wlroots => false,
+        #[cfg(feature = "wayland")]
+        Backend::WlrOutputManagement => false,
+        Backend::Xorg => keyboards
+            .iter()
+            .any(|keyboard| x11::input_device_exists(keyboard).unwrap_or(false)),
+    }
+}
+
+/// Runs `command` and returns its stdout, turning spawn failures, non-zero
+/// exit codes and invalid UTF-8 into readable errors.
+fn command_output(command: &mut Command) -> Result<String, String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .output()
+        .map_err(|e| format!("Unable to run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed ({}): {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    String::from_utf8(output.stdout)
+        .map_err(|e| format!("{} returned invalid UTF-8 output: {}", program, e))
+}
+
+pub fn get_window_server_rotation_state(
+    display: &str,
+    backend: &Backend,
+) -> Result<String, String> {
+    match backend {
+        Backend::Sway => sway::get_outputs()?
+            .into_iter()
+            .find(|output| output.name == display)
+            .map(|output| output.transform)
+            .ok_or_else(|| {
+                format!(
+                    "Unable to determine rotation state: display {} not found in sway outputs",
+                    display
+                )
+            }),
+        Backend::Hyprland => {
+            let raw_rotation_state =
+                command_output(Command::new("hyprctl").arg("-j").arg("monitors"))?;
+            let deserialized: Vec<HyprlandMonitor> = serde_json::from_str(&raw_rotation_state)
+                .map_err(|e| format!("Unable to deserialize hyprctl JSON output: {}", e))?;
+            for monitor in deserialized {
+                if monitor.name == display {
+                    return Ok(wl_transform_name(monitor.transform).to_owned());
+                }
+            }
+
+            Err(format!(
+                "Unable to determine rotation state: display {} not found in 'hyprctl monitors'",
+                display
+            ))
+        }
+        Backend::Cosmic => {
+            let transform = get_cosmic_output(display)?.transform;
+            Ok(match transform.as_str() {
+                "rotate90" => "90".to_owned(),
+                "rotate180" => "180".to_owned(),
+                "rotate270" => "270".to_owned(),
+                "flipped90" => "flipped-90".to_owned(),
+                "flipped180" => "flipped-180".to_owned(),
+                "flipped270" => "flipped-270".to_owned(),
+                _ => transform,
+            })
+        }
+        Backend::Gnome => Ok(wl_transform_name(gnome::get_transform(display)?).to_owned()),
+        Backend::KwinWayland => {
+            let raw_rotation_state = command_output(Command::new("kscreen-doctor").arg("-j"))?;
+            let deserialized: KscreenConfig = serde_json::from_str(&raw_rotation_state)
+                .map_err(|e| format!("Unable to deserialize kscreen-doctor JSON output: {}", e))?;
+            for output in deserialized.outputs {
+                if output.name == display {
+                    // KScreen::Output::Rotation flags
+                    let transform = match output.rotation {
+                        2 => "270",
+                        4 => "180",
+                        8 => "90",
+                        _ => "normal",
+                    };
+                    return Ok(transform.to_owned());
+                }
+            }
+
+            Err(format!(
+                "Unable to determine rotation state: display {} not found in 'kscreen-doctor -j'",
+                display
+            ))
+        }
+        Backend::Niri => {
+            let raw_rotation_state =
+                command_output(Command::new("niri").arg("msg").arg("--json").arg("outputs"))?;
+            let deserialized: HashMap<String, NiriOutput> =
+                serde_json::from_str(&raw_rotation_state)
+                    .map_err(|e| format!("Unable to deserialize niri JSON output: {}", e))?;
+            match deserialized.get(display) {
+                // niri uses the same wl_output transforms as sway, only
+                // spelled differently
+                Some(NiriOutput {
+                    logical: Some(logical),
+                }) => Ok(match logical.transform.to_lowercase().as_str() {
+                    "flipped90" => "flipped-90".to_owned(),
+                    "flipped180" => "flipped-180".to_owned(),
+                    "flipped270" => "flipped-270".to_owned(),
+                    transform => transform.to_owned(),
+                }),
+                Some(_) => Err(format!(
+                    "Unable to determine rotation state: display {} is disabled in niri",
+                    display
+                )),
+                None => Err(format!(
+                    "Unable to determine rotation state: display {} not found in 'niri msg outputs'",
+                    display
+                )),
+            }
+        }
+        Backend::Wlroots => {
+            let raw_rotation_state = command_output(Command::new("wlr-randr").arg("--json"))?;
+            let deserialized: Vec<WlrRandrOutput> = serde_json::from_str(&raw_rotation_state)
+                .map_err(|e| format!("Unable to deserialize wlr-randr JSON output: {}", e))?;
+            for output in deserialized {
+                if output.name == display {
+                    return Ok(output.transform);
+                }
+            }
+
+            Err(format!(
+                "Unable to determine rotation state: display {} not found in 'wlr-randr --json'",
+                display
+            ))
+        }
+        #[cfg(feature = "wayland")]
+        Backend::WlrOutputManagement => {
+            Ok(wl_transform_name(wlr_output::get_transform(display)?).to_owned())
+        }
+        Backend::Xorg => Ok(match x11::get_rotation(display)? {
+            "left" => "270",
+            "inverted" => "180",
+            "right" => "90",
+            _ => "normal",
+        }
+        .to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Detects the backend from the environment variables in `set`, and
+    /// whether sway and X answer.
+    fn from_env(
+        set: &[&str],
+        desktop: &str,
+        sway_answers: bool,
+        x_server_running: bool,
+    ) -> Option<&'static str> {
+        backend_from_env(
+            |name| set.contains(&name),
+            desktop,
+            || sway_answers,
+            || x_server_running,
+        )
+        .map(|(backend, _)| backend.name())
+    }
+
+    fn from_processes(running: &[&str]) -> Option<&'static str> {
+        backend_from_processes(|name| running.contains(&name)).map(|(backend, _)| backend.name())
+    }
+
+    #[test]
+    fn env_compositor_sockets() {
+        let hyprland = Some(Backend::Hyprland.name());
+        assert_eq!(
+            from_env(&["HYPRLAND_INSTANCE_SIGNATURE"], "", false, false),
+            hyprland
+        );
+        // Hyprland also sets $WAYLAND_DISPLAY and runs Xwayland
+        assert_eq!(
+            from_env(
+                &["HYPRLAND_INSTANCE_SIGNATURE", "WAYLAND_DISPLAY", "DISPLAY"],
+                "Hyprland",
+                false,
+                false
+            ),
+            hyprland
+        );
+        assert_eq!(
+            from_env(&["NIRI_SOCKET", "WAYLAND_DISPLAY"], "niri", false, false),
+            Some(Backend::Niri.name())
+        );
+    }
+
+    #[test]
+    fn env_sway_only_when_it_answers() {
+        let sway = Some(Backend::Sway.name());
+        assert_eq!(from_env(&["SWAYSOCK"], "", true, false), sway);
+        assert_eq!(from_env(&["WAYLAND_DISPLAY"], "sway", true, false), sway);
+        // A stale $SWAYSOCK left over in the environment
+        assert_eq!(from_env(&["SWAYSOCK"], "", false, false), None);
+        assert_eq!(
+            from_env(&["SWAYSOCK", "WAYLAND_DISPLAY"], "", false, false),
+            Some(generic_wlroots_backend().name())
+        );
+        // sway isn't even asked without either variable
+        assert_eq!(from_env(&[], "", true, false), None);
+    }
+
+    #[test]
+    fn env_desktops() {
+        let wayland = ["WAYLAND_DISPLAY", "DISPLAY"];
+        assert_eq!(
+            from_env(&wayland, "COSMIC", false, false),
+            Some(Backend::Cosmic.name())
+        );
+        assert_eq!(
+            from_env(&wayland, "ubuntu:GNOME", false, false),
+            Some(Backend::Gnome.name())
+        );
+        assert_eq!(
+            from_env(&wayland, "KDE", false, false),
+            Some(Backend::KwinWayland.name())
+        );
+        // Plasma on X11
+        assert_eq!(
+            from_env(&["DISPLAY"], "KDE", false, true),
+            Some(Backend::Xorg.name())
+        );
+        assert_eq!(
+            from_env(&wayland, "river", false, true),
+            Some(generic_wlroots_backend().name())
+        );
+    }
+
+    #[test]
+    fn env_x_server() {
+        assert_eq!(
+            from_env(&["DISPLAY"], "i3", false, true),
+            Some(Backend::Xorg.name())
+        );
+        // Xwayland, or no X server at all
+        assert_eq!(from_env(&["DISPLAY"], "i3", false, false), None);
+        // Without $DISPLAY there is no X server to ask
+        assert_eq!(from_env(&[], "", false, true), None);
+    }
+
+    #[test]
+    fn processes() {
+        assert_eq!(from_processes(&["sway"]), Some(Backend::Sway.name()));
+        assert_eq!(
+            from_processes(&["Hyprland"]),
+            Some(Backend::Hyprland.name())
+        );
+        assert_eq!(
+            from_processes(&["cosmic-comp"]),
+            Some(Backend::Cosmic.name())
+        );
+        assert_eq!(from_processes(&["niri"]), Some(Backend::Niri.name()));
+        assert_eq!(
+            from_processes(&["gnome-shell"]),
+            Some(Backend::Gnome.name())
+        );
+        assert_eq!(
+            from_processes(&["plasmashell", "kwin_wayland"]),
+            Some(Backend::KwinWayland.name())
+        );
+        // Plasma on X11
+        assert_eq!(
+            from_processes(&["plasmashell", "kwin_x11", "Xorg"]),
+            Some(Backend::Xorg.name())
+        );
+        assert_eq!(
+            from_processes(&["Xwayland"]),
+            Some(generic_wlroots_backend().name())
+        );
+        assert_eq!(from_processes(&["Xorg"]), Some(Backend::Xorg.name()));
+        assert_eq!(from_processes(&["X"]), Some(Backend::Xorg.name()));
+        assert_eq!(from_processes(&[]), None);
+    }
+
+    #[test]
+    fn processes_compositor_before_its_xwayland() {
+        assert_eq!(
+            from_processes(&["Xwayland", "sway"]),
+            Some(Backend::Sway.name())
+        );
+        assert_eq!(
+            from_processes(&["Xwayland", "gnome-shell"]),
+            Some(Backend::Gnome.name())
+        );
+        assert_eq!(
+            from_processes(&["Xorg", "Xwayland"]),
+            Some(generic_wlroots_backend().name())
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+
+use crate::parse_duration;
+
+/// Settings read from the config file. Keys mirror the long command line
+/// flags, and every key is optional.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub backend: Option<String>,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub sleep: Option<String>,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub max_sleep: Option<String>,
+    pub set_sampling_frequency: Option<bool>,
+    pub poll_mode: Option<String>,
+    pub sensor: Option<String>,
+    pub display: Option<String>,
+    pub accel_device: Option<String>,
+    pub accel_name: Option<String>,
+    pub accel_path: Option<String>,
+    pub accel_scale: Option<f32>,
+    pub scale: Option<f32>,
+    pub honor_negative_scale: Option<bool>,
+    pub accel_timeout: Option<u64>,
+    pub touchscreen: Option<String>,
+    pub threshold: Option<f32>,
+    pub release_threshold: Option<f32>,
+    pub classifier: Option<String>,
+    pub deadzone: Option<f32>,
+    pub flat_threshold: Option<f32>,
+    pub shock_tolerance: Option<f32>,
+    pub smoothing: Option<usize>,
+    pub stabilize_ms: Option<u64>,
+    pub stable_samples: Option<u32>,
+    pub keyboard_mode: Option<String>,
+    pub keyboard: Option<String>,
+    pub keyboard_enabled_states: Option<String>,
+    pub rotate_90: Option<bool>,
+    pub flip_y: Option<bool>,
+    pub swap_xy: Option<bool>,
+    pub invert_x: Option<bool>,
+    pub invert_y: Option<bool>,
+    pub invert_z: Option<bool>,
+    pub orientation_map: Option<String>,
+    pub allowed_states: Option<String>,
+    pub rotate_hook: Option<String>,
+    pub hook_normal: Option<String>,
+    pub hook_90: Option<String>,
+    pub hook_180: Option<String>,
+    pub hook_270: Option<String>,
+    pub hook_debounce_ms: Option<u64>,
+    pub inhibit_when_fullscreen: Option<bool>,
+    pub inhibit_app_id: Option<String>,
+    pub pidfile: Option<String>,
+    pub control_socket: Option<String>,
+    pub rotation_lock_key: Option<String>,
+    pub lock_file: Option<String>,
+    pub notify: Option<bool>,
+    pub waybar: Option<bool>,
+    pub restore_on_exit: Option<bool>,
+    pub verbose: Option<u64>,
+    pub quiet: Option<bool>,
+}
+
+/// A duration the way `--sleep` takes it, either a number of millis or a
+/// string like `"500ms"` or `"2s"`. Kept as written for `parse_duration`,
+/// once it is known to parse.
+fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawDuration {
+        Millis(u64),
+        Text(String),
+    }
+
+    let duration = match RawDuration::deserialize(deserializer)? {
+        RawDuration::Millis(millis) => millis.to_string(),
+        RawDuration::Text(text) => text,
+    };
+    match parse_duration(&duration) {
+        Some(_) => Ok(Some(duration)),
+        None => Err(D::Error::custom(format!(
+            "invalid duration '{}', expected e.g. 500, \"500ms\" or \"2s\"",
+            duration
+        ))),
+    }
+}
+
+fn default_config_path() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(config_home.join("rot8").join("config.toml"))
+}
+
+/// Loads the config file from `path`, or from `$XDG_CONFIG_HOME/rot8/config.toml`
+/// when no path is given. A missing default config file yields all defaults.
+pub fn load_config(path: Option<&str>) -> Result<Config, String> {
+    let (path, explicit) = match path {
+        Some(path) => (PathBuf::from(path), true),
+        None => match default_config_path() {
+            Some(path) => (path, false),
+            None => return Ok(Config::default()),
+        },
+    };
+
+    let raw_config = match fs::read_to_string(&path) {
+        Ok(raw_config) => raw_config,
+        Err(e) if e.kind() == ErrorKind::NotFound && !explicit => {
+            return Ok(Config::default());
+        }
+        Err(e) => {
+            return Err(format!(
+                "Unable to read config file {}: {}",
+                path.display(),
+                e
+            ));
+        }
+    };
+
+    toml::from_str(&raw_config)
+        .map_err(|e| format!("Unable to parse config file {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw_config: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(raw_config)
+    }
+
+    #[test]
+    fn durations() {
+        let config = parse("sleep = 250\nmax-sleep = \"2s\"").unwrap();
+        assert_eq!(config.sleep.as_deref(), Some("250"));
+        assert_eq!(config.max_sleep.as_deref(), Some("2s"));
+
+        let config = parse("sleep = \"500ms\"").unwrap();
+        assert_eq!(config.sleep.as_deref(), Some("500ms"));
+        assert_eq!(config.max_sleep, None);
+    }
+
+    #[test]
+    fn invalid_durations() {
+        assert!(parse("sleep = \"soon\"").is_err());
+        assert!(parse("max-sleep = \"5 days\"").is_err());
+        assert!(parse("sleep = -5").is_err());
+    }
+}
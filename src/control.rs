@@ -0,0 +1,179 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use crate::notification;
+use crate::parse_state;
+
+/// What `status` reports, kept up to date by the main loop. Fields are only
+/// ever added, so scripts can rely on the ones there are.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Status {
+    pub backend: String,
+    pub display: String,
+    /// The display's current rotation
+    pub state: String,
+    /// The accelerometer's IIO device directory, or `evdev` or
+    /// `iio-sensor-proxy`
+    pub accelerometer: String,
+    /// The last normalized accelerometer vector
+    pub vector: Option<[f32; 2]>,
+    /// The orientation the accelerometer says to rotate to, or `unknown`
+    pub orientation: String,
+    pub locked: bool,
+    /// Rotations since rot8 started
+    pub rotations: u64,
+    /// Whether this comes from a running rot8
+    pub running: bool,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "backend: {}", self.backend)?;
+        writeln!(f, "display: {} ({})", self.display, self.state)?;
+        writeln!(f, "accelerometer: {}", self.accelerometer)?;
+        match self.vector {
+            Some([x, y]) => writeln!(f, "vector: ({:.3}, {:.3})", x, y)?,
+            None => writeln!(f, "vector: none yet")?,
+        }
+        writeln!(f, "orientation: {}", self.orientation)?;
+        writeln!(f, "locked: {}", if self.locked { "yes" } else { "no" })?;
+        writeln!(f, "rotations: {}", self.rotations)?;
+        write!(f, "running: {}", if self.running { "yes" } else { "no" })
+    }
+}
+
+/// The control socket, removed again when dropped.
+pub struct ControlSocket(PathBuf);
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// `file_name` in `$XDG_RUNTIME_DIR`, e.g. `rot8.sock`
+pub fn runtime_path(file_name: &str) -> Option<PathBuf> {
+    env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| PathBuf::from(dir).join(file_name))
+}
+
+/// Sends `command` to the rot8 listening on `path` and returns its reply.
+pub fn send(path: &Path, command: &str) -> Result<String, String> {
+    let mut stream = UnixStream::connect(path)
+        .map_err(|e| format!("Unable to connect to {}: {}", path.display(), e))?;
+    writeln!(stream, "{}", command)
+        .and_then(|_| stream.shutdown(Shutdown::Write))
+        .map_err(|e| format!("Unable to send to {}: {}", path.display(), e))?;
+
+    let mut reply = String::new();
+    BufReader::new(stream)
+        .read_line(&mut reply)
+        .map_err(|e| format!("Unable to read the reply from {}: {}", path.display(), e))?;
+    Ok(reply.trim_end().to_owned())
+}
+
+/// Listens on `path` for newline separated commands in a background thread:
+/// `status`, `lock`, `unlock`, `toggle-lock` and `set <state>`. Requested
+/// states are sent to the main loop through `requests`, which applies them
+/// between samples.
+pub fn listen(
+    path: PathBuf,
+    status: Arc<Mutex<Status>>,
+    locked: Arc<AtomicBool>,
+    requests: Sender<&'static str>,
+) -> Result<ControlSocket, String> {
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        // Left behind by a rot8 that didn't exit cleanly, unless one is
+        // still listening on it
+        Err(e) if e.kind() == ErrorKind::AddrInUse && UnixStream::connect(&path).is_err() => {
+            fs::remove_file(&path)
+                .and_then(|_| UnixListener::bind(&path))
+                .map_err(|e| format!("Unable to listen on {}: {}", path.display(), e))?
+        }
+        Err(e) => return Err(format!("Unable to listen on {}: {}", path.display(), e)),
+    };
+
+    // Each client gets a thread of its own, so one that never sends or
+    // closes doesn't hold up the others
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let status = Arc::clone(&status);
+            let locked = Arc::clone(&locked);
+            let requests = requests.clone();
+            thread::spawn(move || {
+                if let Err(e) = serve(stream, &status, &locked, &requests) {
+                    debug!("Control connection failed: {}", e);
+                }
+            });
+        }
+    });
+
+    Ok(ControlSocket(path))
+}
+
+/// Locks or unlocks rotation, logging when that changes anything.
+pub fn set_locked(locked: &AtomicBool, lock: bool) {
+    if locked.swap(lock, Ordering::SeqCst) != lock {
+        info!("Rotation {}", if lock { "locked" } else { "unlocked" });
+        notification::lock_changed(lock);
+    }
+}
+
+fn serve(
+    stream: UnixStream,
+    status: &Mutex<Status>,
+    locked: &AtomicBool,
+    requests: &Sender<&'static str>,
+) -> std::io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let reply = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [] => continue,
+            ["status"] => {
+                // The main loop only sees SIGUSR1 and the other commands on
+                // the next sample
+                let mut status = status.lock().unwrap().clone();
+                status.locked |= locked.load(Ordering::SeqCst);
+                serde_json::to_string(&status).unwrap()
+            }
+            ["lock"] => {
+                set_locked(locked, true);
+                "ok".to_owned()
+            }
+            ["unlock"] => {
+                set_locked(locked, false);
+                "ok".to_owned()
+            }
+            ["toggle-lock"] => {
+                set_locked(locked, !locked.load(Ordering::SeqCst));
+                "ok".to_owned()
+            }
+            // Locks rotation too, so the accelerometer doesn't undo it
+            ["set", state] => match parse_state(state) {
+                Ok(state) => {
+                    set_locked(locked, true);
+                    let _ = requests.send(state);
+                    "ok".to_owned()
+                }
+                Err(e) => format!("error: {}", e),
+            },
+            _ => format!("error: unknown command '{}'", line.trim()),
+        };
+        writeln!(writer, "{}", reply)?;
+    }
+    Ok(())
+}
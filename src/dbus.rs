@@ -0,0 +1,84 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use zbus::blocking::{connection, Connection};
+use zbus::{fdo, interface};
+
+use crate::control::{set_locked, Status};
+use crate::parse_state;
+
+const NAME: &str = "org.rot8.Daemon";
+const PATH: &str = "/org/rot8/Daemon";
+
+struct Daemon {
+    status: Arc<Mutex<Status>>,
+    locked: Arc<AtomicBool>,
+    requests: Sender<&'static str>,
+}
+
+#[interface(name = "org.rot8.Daemon")]
+impl Daemon {
+    fn lock(&self) {
+        set_locked(&self.locked, true);
+    }
+
+    fn unlock(&self) {
+        set_locked(&self.locked, false);
+    }
+
+    /// Rotates to `orientation` and locks rotation there, like `set` on the
+    /// control socket.
+    fn set_orientation(&self, orientation: &str) -> fdo::Result<()> {
+        let state = parse_state(orientation).map_err(fdo::Error::InvalidArgs)?;
+        set_locked(&self.locked, true);
+        let _ = self.requests.send(state);
+        Ok(())
+    }
+
+    /// `normal`, `90`, `180` or `270`, as the display is rotated
+    #[zbus(property)]
+    fn orientation(&self) -> String {
+        self.status.lock().unwrap().state.clone()
+    }
+}
+
+/// rot8's `org.rot8.Daemon` name on the session bus.
+pub struct DbusService {
+    connection: Connection,
+}
+
+impl DbusService {
+    /// Takes the bus name and serves the interface from zbus' own thread.
+    pub fn start(
+        status: Arc<Mutex<Status>>,
+        locked: Arc<AtomicBool>,
+        requests: Sender<&'static str>,
+    ) -> Result<Self, String> {
+        let daemon = Daemon {
+            status,
+            locked,
+            requests,
+        };
+        let connection = connection::Builder::session()
+            .and_then(|builder| builder.name(NAME))
+            .and_then(|builder| builder.serve_at(PATH, daemon))
+            .and_then(|builder| builder.build())
+            .map_err(|e| format!("Unable to register {} on the session bus: {}", NAME, e))?;
+        Ok(DbusService { connection })
+    }
+
+    /// Emits `PropertiesChanged` for the orientation.
+    pub fn orientation_changed(&self) {
+        let result = self
+            .connection
+            .object_server()
+            .interface::<_, Daemon>(PATH)
+            .and_then(|iface| {
+                zbus::block_on(iface.get().orientation_changed(iface.signal_emitter()))
+            });
+        if let Err(e) = result {
+            warn!("Unable to signal the orientation change on D-Bus: {}", e);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::io::ErrorKind;
+
+use evdev::{AbsoluteAxisCode, Device, PropType};
+use glob::glob;
+
+/// An accelerometer exposed as an input device with `INPUT_PROP_ACCELEROMETER`,
+/// as on some ARM tablets that have no IIO driver for it.
+pub struct EvdevSensor {
+    device: Device,
+    has_z: bool,
+}
+
+impl EvdevSensor {
+    /// Looks for an accelerometer in `/dev/input`. Returns `Ok(None)` when
+    /// there is none, and an error when one might exist but could not be
+    /// opened.
+    pub fn open() -> Result<Option<Self>, String> {
+        let paths = glob("/dev/input/event*")
+            .map_err(|e| format!("Unable to list input devices: {}", e))?;
+
+        let mut denied = None;
+        for path in paths.flatten() {
+            let device = match Device::open(&path) {
+                Ok(device) => device,
+                Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                    denied.get_or_insert(path);
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            if !device.properties().contains(PropType::ACCELEROMETER) {
+                continue;
+            }
+            let (has_xy, has_z) = match device.supported_absolute_axes() {
+                Some(axes) => (
+                    axes.contains(AbsoluteAxisCode::ABS_X)
+                        && axes.contains(AbsoluteAxisCode::ABS_Y),
+                    axes.contains(AbsoluteAxisCode::ABS_Z),
+                ),
+                None => (false, false),
+            };
+            if has_xy {
+                info!(
+                    "Using evdev accelerometer {} ({})",
+                    path.display(),
+                    device.name().unwrap_or_default()
+                );
+                return Ok(Some(EvdevSensor { device, has_z }));
+            }
+        }
+
+        match denied {
+            Some(path) => Err(format!(
+                "Permission denied opening {}, rot8 needs to be in the input group to read evdev accelerometers",
+                path.display()
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// The resolution of the x axis in units per g, if the driver reports
+    /// one.
+    pub fn resolution(&self) -> Option<i32> {
+        let state = self.device.get_abs_state().ok()?;
+        Some(state[AbsoluteAxisCode::ABS_X.0 as usize].resolution).filter(|res| *res > 0)
+    }
+
+    /// Reads the current x, y and z axis values.
+    pub fn read(&self) -> Result<(i32, i32, Option<i32>), String> {
+        let state = self
+            .device
+            .get_abs_state()
+            .map_err(|e| format!("Unable to read the evdev accelerometer: {}", e))?;
+        let value = |axis: AbsoluteAxisCode| state[axis.0 as usize].value;
+        Ok((
+            value(AbsoluteAxisCode::ABS_X),
+            value(AbsoluteAxisCode::ABS_Y),
+            Some(value(AbsoluteAxisCode::ABS_Z)).filter(|_| self.has_z),
+        ))
+    }
+}
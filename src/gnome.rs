@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::process::Command;
+
+use zbus::blocking::Connection;
+use zbus::zvariant::{OwnedValue, Value};
+
+const DESTINATION: &str = "org.gnome.Mutter.DisplayConfig";
+const PATH: &str = "/org/gnome/Mutter/DisplayConfig";
+const INTERFACE: &str = "org.gnome.Mutter.DisplayConfig";
+
+/// Apply the configuration without storing it in monitors.xml
+const METHOD_TEMPORARY: u32 = 1;
+
+type Properties = HashMap<String, OwnedValue>;
+/// (connector, vendor, product, serial)
+type MonitorSpec = (String, String, String, String);
+/// (id, width, height, refresh rate, preferred scale, supported scales, properties)
+type MonitorMode = (String, i32, i32, f64, f64, Vec<f64>, Properties);
+type Monitor = (MonitorSpec, Vec<MonitorMode>, Properties);
+/// (x, y, scale, transform, primary, monitors, properties)
+type LogicalMonitor = (i32, i32, f64, u32, bool, Vec<MonitorSpec>, Properties);
+type CurrentState = (u32, Vec<Monitor>, Vec<LogicalMonitor>, Properties);
+
+/// (connector, mode id, properties)
+type MonitorConfig<'a> = (String, String, HashMap<&'a str, Value<'a>>);
+/// (x, y, scale, transform, primary, monitors)
+type LogicalMonitorConfig<'a> = (i32, i32, f64, u32, bool, Vec<MonitorConfig<'a>>);
+
+fn get_current_state(connection: &Connection) -> Result<CurrentState, String> {
+    connection
+        .call_method(
+            Some(DESTINATION),
+            PATH,
+            Some(INTERFACE),
+            "GetCurrentState",
+            &(),
+        )
+        .and_then(|reply| reply.body().deserialize())
+        .map_err(|e| format!("Unable to get Mutter display state: {}", e))
+}
+
+fn connect() -> Result<Connection, String> {
+    Connection::session().map_err(|e| format!("Unable to connect to the session bus: {}", e))
+}
+
+/// Returns the connectors of the monitors in use.
+pub fn get_displays() -> Result<Vec<String>, String> {
+    let (_, _, logical_monitors, _) = get_current_state(&connect()?)?;
+    Ok(logical_monitors
+        .into_iter()
+        .flat_map(|logical_monitor| logical_monitor.5)
+        .map(|spec| spec.0)
+        .collect())
+}
+
+/// Returns the wl_output transform of the logical monitor containing `display`.
+pub fn get_transform(display: &str) -> Result<u32, String> {
+    let (_, _, logical_monitors, _) = get_current_state(&connect()?)?;
+    logical_monitors
+        .iter()
+        .find(|logical_monitor| logical_monitor.5.iter().any(|spec| spec.0 == display))
+        .map(|logical_monitor| logical_monitor.3)
+        .ok_or_else(|| {
+            format!(
+                "Unable to determine rotation state: display {} not found in Mutter display state",
+                display
+            )
+        })
+}
+
+fn apply_transform(connection: &Connection, display: &str, transform: u32) -> zbus::Result<()> {
+    let (serial, monitors, logical_monitors, _) =
+        get_current_state(connection).map_err(zbus::Error::Failure)?;
+
+    let current_mode = |spec: &MonitorSpec| {
+        monitors
+            .iter()
+            .find(|monitor| monitor.0 == *spec)
+            .and_then(|monitor| {
+                monitor.1.iter().find(|mode| {
+                    mode.6
+                        .get("is-current")
+                        .and_then(|value| bool::try_from(value).ok())
+                        .unwrap_or(false)
+                })
+            })
+            .map(|mode| mode.0.clone())
+            .unwrap_or_default()
+    };
+
+    // Mutter expects the complete layout, so every logical monitor is sent
+    // back as-is except for the transform of the one containing `display`
+    let config: Vec<LogicalMonitorConfig> = logical_monitors
+        .iter()
+        .map(|(x, y, scale, current_transform, primary, specs, _)| {
+            let contains_display = specs.iter().any(|spec| spec.0 == display);
+            (
+                *x,
+                *y,
+                *scale,
+                if contains_display {
+                    transform
+                } else {
+                    *current_transform
+                },
+                *primary,
+                specs
+                    .iter()
+                    .map(|spec| (spec.0.clone(), current_mode(spec), HashMap::new()))
+                    .collect(),
+            )
+        })
+        .collect();
+
+    connection.call_method(
+        Some(DESTINATION),
+        PATH,
+        Some(INTERFACE),
+        "ApplyMonitorsConfig",
+        &(
+            serial,
+            METHOD_TEMPORARY,
+            config,
+            HashMap::<&str, Value>::new(),
+        ),
+    )?;
+
+    Ok(())
+}
+
+/// Sets the wl_output transform of the logical monitor containing `display`.
+pub fn set_transform(display: &str, transform: u32) -> Result<(), String> {
+    let connection = connect()?;
+
+    let result = match apply_transform(&connection, display, transform) {
+        // The configuration serial went stale between reading the state and
+        // applying it, try once more with a fresh state
+        Err(zbus::Error::MethodError(name, _, _))
+            if name.as_str() == "org.freedesktop.DBus.Error.AccessDenied" =>
+        {
+            apply_transform(&connection, display, transform)
+        }
+        result => result,
+    };
+
+    result.map_err(|e| format!("Unable to rotate display {} with Mutter: {}", display, e))
+}
+
+/// Whether GNOME's own auto-rotation is on, i.e. the orientation lock in the
+/// quick settings is off.
+pub fn auto_rotation_enabled() -> bool {
+    Command::new("gsettings")
+        .arg("get")
+        .arg("org.gnome.settings-daemon.peripherals.touchscreen")
+        .arg("orientation-lock")
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).trim() == "false")
+}
@@ -0,0 +1,306 @@
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use glob::glob;
+
+/// Layout of a single channel inside a buffered IIO sample, as described by
+/// its `scan_elements/*_type` file (e.g. `le:s12/16>>4`).
+struct ScanElement {
+    index: u32,
+    offset: usize,
+    big_endian: bool,
+    signed: bool,
+    bits: u32,
+    storage_bytes: usize,
+    repeat: usize,
+    shift: u32,
+}
+
+impl ScanElement {
+    fn read(scan_dir: &Path, channel: &str) -> Result<Self, String> {
+        let read_attribute = |suffix: &str| {
+            let path = scan_dir.join(format!("{}_{}", channel, suffix));
+            fs::read_to_string(&path)
+                .map(|value| value.trim().to_owned())
+                .map_err(|e| format!("Unable to read {}: {}", path.display(), e))
+        };
+
+        let index = read_attribute("index")?
+            .parse::<u32>()
+            .map_err(|e| format!("Invalid scan index for {}: {}", channel, e))?;
+        let raw_type = read_attribute("type")?;
+        let invalid_type = || format!("Invalid scan type '{}' for {}", raw_type, channel);
+
+        let (endianness, format) = raw_type.split_once(':').ok_or_else(invalid_type)?;
+        let (sign, format) = format.split_at(1);
+        let (bits, format) = format.split_once('/').ok_or_else(invalid_type)?;
+        let (storage, shift) = format.split_once(">>").ok_or_else(invalid_type)?;
+        let (storage, repeat) = match storage.split_once('X') {
+            Some((storage, repeat)) => (storage, repeat),
+            None => (storage, "1"),
+        };
+
+        let storage_bits = storage.parse::<u32>().map_err(|_| invalid_type())?;
+        if storage_bits == 0 || storage_bits > 64 || storage_bits % 8 != 0 {
+            return Err(invalid_type());
+        }
+
+        Ok(ScanElement {
+            index,
+            offset: 0,
+            big_endian: endianness == "be",
+            signed: sign == "s",
+            bits: bits.parse::<u32>().map_err(|_| invalid_type())?,
+            storage_bytes: storage_bits as usize / 8,
+            repeat: repeat.parse::<usize>().map_err(|_| invalid_type())?,
+            shift: shift.parse::<u32>().map_err(|_| invalid_type())?,
+        })
+    }
+
+    fn decode(&self, sample: &[u8]) -> i32 {
+        let bytes = &sample[self.offset..self.offset + self.storage_bytes];
+        let mut value: u64 = 0;
+        if self.big_endian {
+            for byte in bytes {
+                value = (value << 8) | u64::from(*byte);
+            }
+        } else {
+            for byte in bytes.iter().rev() {
+                value = (value << 8) | u64::from(*byte);
+            }
+        }
+
+        value >>= self.shift;
+        if self.bits < 64 {
+            value &= (1u64 << self.bits) - 1;
+        }
+
+        if self.signed && self.bits < 64 && value & (1u64 << (self.bits - 1)) != 0 {
+            (value as i64 - (1i64 << self.bits)) as i32
+        } else {
+            value as i32
+        }
+    }
+}
+
+/// Blocking reader for the buffered interface (`/dev/iio:deviceN`) of an IIO
+/// accelerometer. Only the x, y and (if there is one) z channels are enabled.
+pub struct IioBuffer {
+    device: PathBuf,
+    file: File,
+    sample_size: usize,
+    x: ScanElement,
+    y: ScanElement,
+    z: Option<ScanElement>,
+    /// The latest sample, to return again when no new one comes in time
+    last: Option<(i32, i32, Option<i32>)>,
+}
+
+impl IioBuffer {
+    /// Sets up and enables the buffer of the IIO device at `device`, e.g.
+    /// `/sys/bus/iio/devices/iio:device0`.
+    pub fn open(device: &Path) -> Result<Self, String> {
+        let scan_dir = device.join("scan_elements");
+        if !scan_dir.is_dir() {
+            return Err(format!(
+                "{} does not support buffered reads",
+                device.display()
+            ));
+        }
+
+        // Make sure the buffer is off while we change its configuration
+        let _ = fs::write(device.join("buffer/enable"), "0");
+
+        let scan_enables = glob(&format!("{}/*_en", scan_dir.display()))
+            .map_err(|e| format!("Unable to list scan elements: {}", e))?;
+        for path in scan_enables.flatten() {
+            let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+            let enable = file_name == "in_accel_x_en"
+                || file_name == "in_accel_y_en"
+                || file_name == "in_accel_z_en";
+            fs::write(&path, if enable { "1" } else { "0" })
+                .map_err(|e| format!("Unable to write {}: {}", path.display(), e))?;
+        }
+
+        let mut x = ScanElement::read(&scan_dir, "in_accel_x")?;
+        let mut y = ScanElement::read(&scan_dir, "in_accel_y")?;
+        let mut z = if scan_dir.join("in_accel_z_en").is_file() {
+            Some(ScanElement::read(&scan_dir, "in_accel_z")?)
+        } else {
+            None
+        };
+
+        // Channels are laid out by scan index, each aligned to its storage size
+        let mut offset = 0;
+        let mut alignment = 1;
+        let mut elements = vec![&mut x, &mut y];
+        elements.extend(z.as_mut());
+        elements.sort_by_key(|element| element.index);
+        for element in elements.iter_mut() {
+            offset = align(offset, element.storage_bytes);
+            element.offset = offset;
+            offset += element.storage_bytes * element.repeat;
+            alignment = alignment.max(element.storage_bytes);
+        }
+        let sample_size = align(offset, alignment);
+
+        set_default_trigger(device)?;
+
+        fs::write(device.join("buffer/enable"), "1")
+            .map_err(|e| format!("Unable to enable the buffer of {}: {}", device.display(), e))?;
+
+        let dev_path = Path::new("/dev").join(device.file_name().unwrap());
+        let file = File::open(&dev_path)
+            .map_err(|e| format!("Unable to open {}: {}", dev_path.display(), e))?;
+
+        Ok(IioBuffer {
+            device: device.to_owned(),
+            file,
+            sample_size,
+            x,
+            y,
+            z,
+            last: None,
+        })
+    }
+
+    /// Waits up to `timeout` for the next sample and returns its raw x, y
+    /// and z values, or those of the previous sample if none came in time.
+    /// Returns `None` if there hasn't been any sample yet.
+    pub fn read(&mut self, timeout: Duration) -> Result<Option<(i32, i32, Option<i32>)>, String> {
+        let device = &self.device;
+        let error = |e: io::Error| {
+            format!(
+                "Unable to read from the buffer of {}: {}",
+                device.display(),
+                e
+            )
+        };
+        if !wait_readable(&self.file, timeout).map_err(error)? {
+            return Ok(self.last);
+        }
+
+        let mut sample = vec![0; self.sample_size];
+        self.file.read_exact(&mut sample).map_err(error)?;
+        self.last = Some((
+            self.x.decode(&sample),
+            self.y.decode(&sample),
+            self.z.as_ref().map(|z| z.decode(&sample)),
+        ));
+        Ok(self.last)
+    }
+}
+
+impl Drop for IioBuffer {
+    fn drop(&mut self) {
+        let _ = fs::write(self.device.join("buffer/enable"), "0");
+    }
+}
+
+/// Waits up to `timeout` for `file` to have something to read, or to fail
+/// reading, as it does once the device is gone.
+fn wait_readable(file: &File, timeout: Duration) -> io::Result<bool> {
+    let mut fd = libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout = libc::c_int::try_from(timeout.as_millis()).unwrap_or(libc::c_int::MAX);
+    // SAFETY: `fd` is a single pollfd that outlives the call
+    match unsafe { libc::poll(&mut fd, 1, timeout) } {
+        -1 => {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                Ok(false)
+            } else {
+                Err(e)
+            }
+        }
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+fn align(offset: usize, alignment: usize) -> usize {
+    offset.div_ceil(alignment) * alignment
+}
+
+/// Name of the hrtimer trigger created when a device has no trigger of its own
+const HRTIMER_TRIGGER: &str = "rot8";
+const HRTIMER_CONFIGFS: &str = "/sys/kernel/config/iio/triggers/hrtimer";
+/// Samples per second taken through the hrtimer trigger
+const HRTIMER_FREQUENCY: &str = "10";
+
+fn find_trigger(name: &str) -> Result<Option<PathBuf>, String> {
+    let triggers = glob("/sys/bus/iio/devices/trigger*/name")
+        .map_err(|e| format!("Unable to list IIO triggers: {}", e))?;
+    Ok(triggers
+        .flatten()
+        .find(|path| fs::read_to_string(path).unwrap_or_default().trim() == name)
+        .map(|path| path.parent().unwrap().to_owned()))
+}
+
+/// Creates (or reuses) an hrtimer trigger through configfs.
+fn hrtimer_trigger() -> Result<Option<PathBuf>, String> {
+    let config_dir = Path::new(HRTIMER_CONFIGFS).join(HRTIMER_TRIGGER);
+    if !config_dir.is_dir() {
+        if let Err(e) = fs::create_dir(&config_dir) {
+            debug!("Unable to create {}: {}", config_dir.display(), e);
+            return Ok(None);
+        }
+    }
+
+    let trigger = find_trigger(HRTIMER_TRIGGER)?;
+    if let Some(trigger) = &trigger {
+        let _ = fs::write(trigger.join("sampling_frequency"), HRTIMER_FREQUENCY);
+    }
+    Ok(trigger)
+}
+
+/// Assigns the device's own data-ready trigger (`<name>-devN`) if the device
+/// needs a trigger and none is set yet, or an hrtimer trigger if it has none.
+fn set_default_trigger(device: &Path) -> Result<(), String> {
+    let current_trigger_path = device.join("trigger/current_trigger");
+    let current_trigger = match fs::read_to_string(&current_trigger_path) {
+        Ok(current_trigger) => current_trigger,
+        // The device doesn't use triggers
+        Err(_) => return Ok(()),
+    };
+    if !current_trigger.trim().is_empty() {
+        return Ok(());
+    }
+
+    let name = fs::read_to_string(device.join("name")).unwrap_or_default();
+    let device_number = device
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .trim_start_matches("iio:device")
+        .to_owned();
+    let device_trigger = format!("{}-dev{}", name.trim(), device_number);
+
+    let trigger_name = if find_trigger(&device_trigger)?.is_some() {
+        device_trigger
+    } else if hrtimer_trigger()?.is_some() {
+        HRTIMER_TRIGGER.to_owned()
+    } else {
+        return Err(format!(
+            "No trigger set for {}, no trigger named {} found and no hrtimer trigger could be created",
+            device.display(),
+            device_trigger
+        ));
+    };
+
+    fs::write(&current_trigger_path, &trigger_name).map_err(|e| {
+        format!(
+            "Unable to set trigger {} for {}: {}",
+            trigger_name,
+            device.display(),
+            e
+        )
+    })
+}
@@ -0,0 +1,118 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use evdev::{Device, EventSummary, KeyCode, SwitchCode};
+use glob::glob;
+
+use crate::control::set_locked;
+
+/// `KEY_ROTATE_LOCK_TOGGLE`, which evdev has no name for
+const KEY_ROTATE_LOCK_TOGGLE: KeyCode = KeyCode::new(0x231);
+
+fn has_key(device: &Device) -> bool {
+    device
+        .supported_keys()
+        .is_some_and(|keys| keys.contains(KEY_ROTATE_LOCK_TOGGLE))
+}
+
+fn has_switch(device: &Device) -> bool {
+    device
+        .supported_switches()
+        .is_some_and(|switches| switches.contains(SwitchCode::SW_ROTATE_LOCK))
+}
+
+fn open_error(path: &Path, e: std::io::Error) -> String {
+    if e.kind() == ErrorKind::PermissionDenied {
+        format!(
+            "Permission denied opening {}, rot8 needs to be in the input group to read the \
+            rotation lock key",
+            path.display()
+        )
+    } else {
+        format!("Unable to open {}: {}", path.display(), e)
+    }
+}
+
+/// Opens the input device at `spec`, or with `auto` the first one with a
+/// rotation lock key or switch.
+fn open(spec: &str) -> Result<(Device, PathBuf), String> {
+    if spec != "auto" {
+        let path = PathBuf::from(spec);
+        let device = Device::open(&path).map_err(|e| open_error(&path, e))?;
+        if !has_key(&device) && !has_switch(&device) {
+            return Err(format!(
+                "{} has no rotation lock key or switch",
+                path.display()
+            ));
+        }
+        return Ok((device, path));
+    }
+
+    let paths =
+        glob("/dev/input/event*").map_err(|e| format!("Unable to list input devices: {}", e))?;
+    let mut denied = None;
+    for path in paths.flatten() {
+        match Device::open(&path) {
+            Ok(device) if has_key(&device) || has_switch(&device) => return Ok((device, path)),
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                denied.get_or_insert((path, e));
+            }
+            Err(_) => {}
+        }
+    }
+
+    Err(match denied {
+        Some((path, e)) => open_error(&path, e),
+        None => "No input device with a rotation lock key or switch found".to_owned(),
+    })
+}
+
+/// Locks rotation as the rotation lock switch at `spec` says, starting from
+/// where it is now, or toggles the lock on every press of the rotation lock
+/// key, from a background thread.
+pub fn watch(spec: &str, locked: Arc<AtomicBool>) -> Result<(), String> {
+    let (mut device, path) = open(spec)?;
+    info!(
+        "Following the rotation lock {} of {} ({})",
+        if has_switch(&device) { "switch" } else { "key" },
+        path.display(),
+        device.name().unwrap_or_default()
+    );
+    if has_switch(&device) {
+        match device.get_switch_state() {
+            Ok(switches) => set_locked(&locked, switches.contains(SwitchCode::SW_ROTATE_LOCK)),
+            Err(e) => warn!("Unable to read the rotation lock switch: {}", e),
+        }
+    }
+
+    thread::spawn(move || loop {
+        let events = match device.fetch_events() {
+            Ok(events) => events,
+            Err(e) => {
+                warn!(
+                    "Unable to read the rotation lock key from {}: {}",
+                    path.display(),
+                    e
+                );
+                return;
+            }
+        };
+        for event in events {
+            match event.destructure() {
+                EventSummary::Key(_, code, 1) if code == KEY_ROTATE_LOCK_TOGGLE => {
+                    set_locked(&locked, !locked.load(Ordering::SeqCst))
+                }
+                EventSummary::Switch(_, SwitchCode::SW_ROTATE_LOCK, value) => {
+                    set_locked(&locked, value != 0)
+                }
+                _ => {}
+            }
+        }
+    });
+
+    Ok(())
+}
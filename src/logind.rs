@@ -0,0 +1,70 @@
+use zbus::blocking::{proxy, Connection, Proxy};
+use zbus::proxy::CacheProperties;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath};
+
+const DESTINATION: &str = "org.freedesktop.login1";
+const SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+const USER_INTERFACE: &str = "org.freedesktop.login1.User";
+
+fn logind_error(e: zbus::Error) -> String {
+    format!("logind request failed: {}", e)
+}
+
+/// Reads the session's properties fresh every time rather than trusting
+/// logind to signal every change.
+fn session_proxy(
+    connection: &Connection,
+    path: ObjectPath<'static>,
+) -> zbus::Result<Proxy<'static>> {
+    proxy::Builder::new(connection)
+        .destination(DESTINATION)?
+        .path(path)?
+        .interface(SESSION_INTERFACE)?
+        .cache_properties(CacheProperties::No)
+        .build()
+}
+
+/// The logind session rot8 rotates the display of, to tell when its screen
+/// is locked.
+pub struct Session {
+    proxy: Proxy<'static>,
+}
+
+impl Session {
+    /// Finds rot8's own session, or the user's graphical one when rot8 runs
+    /// outside of any session, e.g. as a systemd user service.
+    pub fn open() -> Result<Self, String> {
+        let connection = Connection::system()
+            .map_err(|e| format!("Unable to connect to the system bus: {}", e))?;
+
+        let own = session_proxy(
+            &connection,
+            ObjectPath::from_static_str_unchecked("/org/freedesktop/login1/session/auto"),
+        )
+        .map_err(logind_error)?;
+        if own.get_property::<bool>("LockedHint").is_ok() {
+            return Ok(Session { proxy: own });
+        }
+
+        let user = Proxy::new(
+            &connection,
+            DESTINATION,
+            "/org/freedesktop/login1/user/self",
+            USER_INTERFACE,
+        )
+        .map_err(logind_error)?;
+        let (id, path): (String, OwnedObjectPath) =
+            user.get_property("Display").map_err(logind_error)?;
+        if id.is_empty() {
+            return Err("logind knows of no graphical session to watch".to_owned());
+        }
+        let proxy = session_proxy(&connection, path.into()).map_err(logind_error)?;
+        debug!("Watching logind session {} for screen locks", id);
+        Ok(Session { proxy })
+    }
+
+    /// Whether the screen locker is up, as far as it tells logind.
+    pub fn locked(&self) -> Result<bool, String> {
+        self.proxy.get_property("LockedHint").map_err(logind_error)
+    }
+}
@@ -1,21 +1,53 @@
 extern crate clap;
 extern crate glob;
-extern crate regex;
+#[macro_use]
+extern crate log;
 
+mod backend;
+mod config;
+mod control;
+mod dbus;
+mod evdev_sensor;
+mod gnome;
+mod iio;
+mod lock_key;
+mod logind;
+mod notification;
+mod sensor;
+mod sensor_proxy;
+mod smoothing;
+mod sway;
+#[cfg(feature = "systemd")]
+mod systemd;
+#[cfg(feature = "wayland")]
+mod wlr_output;
+mod x11;
+
+use std::collections::HashMap;
 use std::fs;
-use std::process::{Command, Stdio};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use clap::{App, Arg};
-use glob::glob;
-use serde::Deserialize;
-use serde_json::Value;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1, SIGUSR2};
+use signal_hook::iterator::Signals;
 
-enum Backend {
-    Sway,
-    Xorg,
-}
+use backend::{
+    check_backend_tools, command_available, get_cosmic_output, get_displays, get_fullscreen_apps,
+    get_keyboards, get_touchscreens, get_window_server_rotation_state, keyboards_attached,
+    parse_backend, process_running, Backend,
+};
+use config::{load_config, Config};
+use dbus::DbusService;
+use env_logger::Env;
+use sensor::{AxisRemap, PollMode, Sample, Sensor, SensorOptions};
+use smoothing::MovingAverage;
 
 enum KeyboardMode {
     Integrated,
@@ -23,196 +55,1152 @@ enum KeyboardMode {
     None,
 }
 
-#[derive(Deserialize)]
-struct SwayOutput {
-    name: String,
-    transform: String,
+/// Spawns `command` and waits for it, or only prints it with --dry-run.
+fn run(command: &mut Command, dry_run: bool) {
+    if dry_run {
+        println!("{:?}", command);
+        return;
+    }
+    match command.status() {
+        Ok(status) if !status.success() => error!("{:?} failed with {}", command, status),
+        Ok(_) => {}
+        Err(e) => error!("Unable to run {:?}: {}", command, e),
+    }
 }
 
-fn get_keyboards(backend: &Backend) -> Result<Vec<String>, String> {
-    match backend {
-        Backend::Sway => {
-            let raw_inputs = String::from_utf8(
-                Command::new("swaymsg")
-                    .arg("-t")
-                    .arg("get_inputs")
-                    .arg("--raw")
-                    .output()
-                    .expect("Swaymsg get inputs command failed")
-                    .stdout,
+/// Returns the value of an argument, preferring the command line over the
+/// config file, and the config file over the built-in default.
+fn arg_value(matches: &ArgMatches, name: &str, config_value: Option<String>) -> Option<String> {
+    if matches.occurrences_of(name) > 0 {
+        matches.value_of(name).map(String::from)
+    } else {
+        config_value.or_else(|| matches.value_of(name).map(String::from))
+    }
+}
+
+/// How often to check the display's rotation for changes made by others
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often to look for keyboards that were plugged in or removed
+const KEYBOARD_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How far the vector has to move between samples for the device to count as
+/// moving, squared
+const MOTION_DELTA: f32 = 0.01;
+
+const STATES: [&str; 4] = ["normal", "90", "180", "270"];
+
+fn parse_state(state: &str) -> Result<&'static str, String> {
+    STATES
+        .iter()
+        .find(|known| **known == state)
+        .copied()
+        .ok_or_else(|| {
+            format!(
+                "Unknown orientation '{}', expected one of normal, 90, 180 and 270",
+                state
             )
-            .unwrap();
+        })
+}
 
-            let mut keyboards = vec![];
-            let deserialized: Vec<Value> = serde_json::from_str(&raw_inputs)
-                .expect("Unable to deserialize swaymsg JSON output");
-            for output in deserialized {
-                let input_type = output["type"].as_str().unwrap();
-                if input_type == "keyboard" {
-                    keyboards.push(output["identifier"].to_string());
-                }
-            }
+/// Parses a duration like `500ms`, `2s` or `1.5s`, taking a bare number for
+/// milliseconds like `--sleep` always has.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (number, unit) = value.split_at(
+        value
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(value.len()),
+    );
+    let number = number.parse::<f64>().ok()?;
+    let seconds = match unit.trim() {
+        "" | "ms" => number / 1000.0,
+        "s" => number,
+        "m" | "min" => number * 60.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// Parses an orientation map like `normal=90,90=180`, which maps the
+/// orientation the accelerometer reports to the one to apply. Orientations
+/// that aren't mentioned are applied as they are.
+fn parse_orientation_map(spec: &str) -> Result<HashMap<&'static str, &'static str>, String> {
+    spec.split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (from, to) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid orientation map entry '{}'", entry))?;
+            Ok((parse_state(from.trim())?, parse_state(to.trim())?))
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug)]
+struct Orientation {
+    vector: (f32, f32),
+    new_state: &'static str,
+    x_state: &'static str,
+    wl_transform: u32,
+    kde_state: &'static str,
+    niri_state: &'static str,
+    cosmic_state: &'static str,
+}
+
+/// The orientations rot8 tells apart, by the accelerometer vector that
+/// points down in each.
+fn orientations() -> [Orientation; 4] {
+    [
+        Orientation {
+            vector: (0.0, -1.0),
+            new_state: "normal",
+            x_state: "normal",
+            wl_transform: 0,
+            kde_state: "none",
+            niri_state: "normal",
+            cosmic_state: "normal",
+        },
+        Orientation {
+            vector: (0.0, 1.0),
+            new_state: "180",
+            x_state: "inverted",
+            wl_transform: 2,
+            kde_state: "inverted",
+            niri_state: "180",
+            cosmic_state: "rotate180",
+        },
+        Orientation {
+            vector: (-1.0, 0.0),
+            new_state: "90",
+            x_state: "right",
+            wl_transform: 1,
+            kde_state: "right",
+            niri_state: "90",
+            cosmic_state: "rotate90",
+        },
+        Orientation {
+            vector: (1.0, 0.0),
+            new_state: "270",
+            x_state: "left",
+            wl_transform: 3,
+            kde_state: "left",
+            niri_state: "270",
+            cosmic_state: "rotate270",
+        },
+    ]
+}
 
-            return Ok(keyboards);
+/// The xinput coordinate transformation matrix for touch input on a display
+/// with the wl_output `transform`: turned by a quarter turn per step, after
+/// mirroring horizontally for the flipped transforms 4 to 7, and moved back
+/// into the unit square.
+fn transform_matrix(transform: u32) -> [f32; 9] {
+    let (cos, sin): (i8, i8) = match transform % 4 {
+        0 => (1, 0),
+        1 => (0, 1),
+        2 => (-1, 0),
+        _ => (0, -1),
+    };
+    let (cos, sin, minus_sin) = (f32::from(cos), f32::from(sin), f32::from(-sin));
+    let mut matrix = [
+        cos,
+        sin,
+        (1.0 - cos - sin) / 2.0,
+        minus_sin,
+        cos,
+        (1.0 - cos - minus_sin) / 2.0,
+        0.0,
+        0.0,
+        1.0,
+    ];
+    if transform >= 4 {
+        // x becomes 1 - x before anything else
+        for row in [0, 3] {
+            matrix[row + 2] += matrix[row];
+            matrix[row] = -matrix[row];
         }
-        Backend::Xorg => {
-            return Ok(vec![]);
+    }
+    matrix
+}
+
+/// The squared distance between `(x, y)` and the vector of `orient`.
+fn distance(orient: &Orientation, (x, y): (f32, f32)) -> f32 {
+    (x - orient.vector.0).powf(2.0) + (y - orient.vector.1).powf(2.0)
+}
+
+/// The angle in degrees between `(x, y)` and the vector of `orient`.
+fn angle(orient: &Orientation, (x, y): (f32, f32)) -> f32 {
+    let diff = (y.atan2(x) - orient.vector.1.atan2(orient.vector.0))
+        .to_degrees()
+        .abs();
+    diff.min(360.0 - diff)
+}
+
+/// The release threshold when none is given. Once the vector is past the 45°
+/// boundary, its squared distance to the current orientation is at least
+/// 2 - √2 ≈ 0.586, so the default has to be above that to hold anything: 0.7
+/// holds the current orientation up to about 49.5°.
+fn default_release_threshold(threshold: f32) -> f32 {
+    threshold.max(0.7)
+}
+
+/// How a sample is judged close enough to its nearest orientation.
+enum Classifier {
+    /// Within `threshold` of the orientation's vector, and for leaving the
+    /// current orientation, further than `release_threshold` from it
+    Distance {
+        threshold: f32,
+        release_threshold: f32,
+    },
+    /// Within the orientation's 90° sector, but more than `deadzone` degrees
+    /// from its edges
+    Angle { deadzone: f32 },
+}
+
+impl Classifier {
+    fn accepts(&self, orient: &Orientation, vector: (f32, f32)) -> bool {
+        match *self {
+            Classifier::Distance { threshold, .. } => distance(orient, vector) < threshold,
+            Classifier::Angle { deadzone } => angle(orient, vector) < 45.0 - deadzone,
         }
     }
 }
 
-fn keyboards_attached<T: AsRef<std::ffi::OsStr>>(backend: &Backend, keyboards: &[T]) -> bool {
-    match backend {
-        Backend::Sway => {
-            // TODO
-            false
+/// Returns the orientation closest to `(x, y)` and its squared distance.
+/// Being the closest by distance, it is also the closest by angle.
+fn nearest_orientation(orientations: &[Orientation], vector: (f32, f32)) -> (&Orientation, f32) {
+    orientations
+        .iter()
+        .map(|orient| (orient, distance(orient, vector)))
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .unwrap()
+}
+
+/// Adds the PineTab hacks after the axis flags: --flip-y inverts y, and
+/// --rotate-90 then turns the result a quarter clockwise, i.e.
+/// (x, y) -> (y, -x).
+fn pinetab_axes(axes: AxisRemap, rotate_90: bool, flip_y: bool) -> AxisRemap {
+    // Turning swaps the axes, and the inversions of the flags go with them
+    let (invert_x, invert_y) = if rotate_90 {
+        (axes.invert_y, axes.invert_x)
+    } else {
+        (axes.invert_x, axes.invert_y)
+    };
+    AxisRemap {
+        swap_xy: axes.swap_xy ^ rotate_90,
+        invert_x: invert_x ^ (rotate_90 && flip_y),
+        invert_y: invert_y ^ (rotate_90 || flip_y),
+        ..axes
+    }
+}
+
+/// The state the display is in while the device is upright, where the
+/// integrated keyboard stays enabled by default. Only `--rotate-90` turns it,
+/// for the PineTab's sideways panel, as the other axis flags correct how the
+/// accelerometer is mounted and leave upright where it is.
+fn human_normal(rotate_90: bool, orientation_map: &HashMap<&str, &'static str>) -> &'static str {
+    orientation_map[if rotate_90 { "90" } else { "normal" }]
+}
+
+/// An orientation waiting to hold for long enough before it is applied.
+struct Pending {
+    state: &'static str,
+    since: Instant,
+    /// Samples in a row that matched an orientation
+    samples: u32,
+}
+
+impl Pending {
+    fn new(state: &'static str, matched: bool) -> Self {
+        Pending {
+            state,
+            since: Instant::now(),
+            samples: matched as u32,
         }
-        Backend::Xorg => {
-            for keyboard in keyboards {
-                let probe = Command::new("xinput")
-                    .arg("list")
-                    .arg(&keyboard)
-                    .stdout(Stdio::null())
-                    .stderr(Stdio::null())
-                    .status()
-                    .expect("Xinput list command failed to start");
-
-                if probe.success() {
-                    return true;
-                }
+    }
+
+    /// Counts another sample towards `state`. Samples that matched no
+    /// orientation break the streak.
+    fn observe(&mut self, matched: bool) {
+        if matched {
+            self.samples += 1;
+        } else {
+            self.samples = 0;
+        }
+    }
+
+    fn is_stable(&self, stabilize: Duration, stable_samples: u32) -> bool {
+        self.since.elapsed() >= stabilize && self.samples >= stable_samples
+    }
+}
+
+/// Waits for a new orientation to hold for `stabilize` and `stable_samples`
+/// before rotating, and forgets about it if the device goes back to
+/// `old_state` in the meantime. Returns whether `new_state` is stable.
+fn debounce(
+    pending: &mut Option<Pending>,
+    old_state: &str,
+    new_state: &'static str,
+    matched: bool,
+    stabilize: Duration,
+    stable_samples: u32,
+) -> bool {
+    match pending {
+        _ if new_state == old_state => *pending = None,
+        Some(candidate) if candidate.state == new_state => candidate.observe(matched),
+        _ => *pending = Some(Pending::new(new_state, matched)),
+    }
+    pending
+        .as_ref()
+        .is_some_and(|candidate| candidate.is_stable(stabilize, stable_samples))
+}
+
+/// How many samples `calibrate` averages in each orientation
+const CALIBRATION_SAMPLES: usize = 10;
+
+/// The orientation map that turns each detected orientation into the one the
+/// device was measured in: `measured` pairs each state with the vector read
+/// while holding the device that way.
+fn calibration_map(
+    orientations: &[Orientation],
+    measured: &[(&'static str, (f32, f32))],
+) -> Vec<(&'static str, &'static str)> {
+    measured
+        .iter()
+        .map(|(state, vector)| {
+            (
+                nearest_orientation(orientations, *vector).0.new_state,
+                *state,
+            )
+        })
+        .collect()
+}
+
+/// Asks for the device to be held in each orientation in turn, and prints
+/// the measured vectors and the `orientation-map` for the config file.
+fn calibrate(
+    sensor: &mut Sensor,
+    axes: &AxisRemap,
+    orientations: &[Orientation],
+    interval: Duration,
+) -> Result<(), String> {
+    let positions = [
+        ("normal", "upright"),
+        (
+            "90",
+            "turned a quarter to the left, with its top edge pointing left",
+        ),
+        ("180", "upside down"),
+        (
+            "270",
+            "turned a quarter to the right, with its top edge pointing right",
+        ),
+    ];
+
+    let mut measured = vec![];
+    for (state, position) in positions {
+        eprint!("Hold the device {} and press Enter", position);
+        io::stdin()
+            .read_line(&mut String::new())
+            .map_err(|e| format!("Unable to read from stdin: {}", e))?;
+
+        let mut smoother = MovingAverage::new(CALIBRATION_SAMPLES);
+        let mut vector = (0.0, 0.0);
+        for _ in 0..CALIBRATION_SAMPLES {
+            vector = smoother.push(axes.apply(sensor.read(interval)?).vector);
+            if sensor.polls() {
+                thread::sleep(interval);
             }
-            return false;
         }
+        measured.push((state, vector));
+    }
+
+    let map = calibration_map(orientations, &measured);
+    println!("# Measured vectors:");
+    for (state, (x, y)) in &measured {
+        println!("# {}: ({:.3}, {:.3})", state, x, y);
+    }
+    let mut detected: Vec<&str> = map.iter().map(|(detected, _)| *detected).collect();
+    detected.sort_unstable();
+    detected.dedup();
+    if detected.len() < map.len() {
+        return Err(
+            "Several orientations read as the same one, hold the device upright rather than \
+            flat and try again"
+                .to_owned(),
+        );
+    }
+    println!(
+        "orientation-map = \"{}\"",
+        map.iter()
+            .map(|(detected, state)| format!("{}={}", detected, state))
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+
+    Ok(())
+}
+
+/// The input devices that turn along with the display.
+struct RotatedInputs<'a> {
+    /// The X11 touchscreen
+    touchscreen: &'a str,
+    /// Whether to set the touchscreen matrix on Xorg
+    touch_matrix: bool,
+    /// The touch inputs to map to the display on sway
+    touchscreens: &'a [String],
+    keyboard_mode: &'a KeyboardMode,
+    keyboards: &'a [String],
+    /// Whether the integrated keyboard stays enabled in the new orientation
+    keyboard_enabled: bool,
+}
+
+impl RotatedInputs<'_> {
+    /// For the displays after the first, which turn on their own.
+    fn none() -> RotatedInputs<'static> {
+        RotatedInputs {
+            touchscreen: "",
+            touch_matrix: false,
+            touchscreens: &[],
+            keyboard_mode: &KeyboardMode::None,
+            keyboards: &[],
+            keyboard_enabled: true,
+        }
+    }
+}
+
+/// Looks up the rotation of each of `displays`, leaving out the ones the
+/// backend doesn't know with a warning, unless there is only the one.
+fn find_displays(backend: &Backend, displays: &[String]) -> Result<Vec<(String, String)>, String> {
+    if let [display] = displays {
+        let state = get_window_server_rotation_state(display, backend)?;
+        return Ok(vec![(display.clone(), state)]);
+    }
+
+    let found: Vec<_> = displays
+        .iter()
+        .filter_map(
+            |display| match get_window_server_rotation_state(display, backend) {
+                Ok(state) => Some((display.clone(), state)),
+                Err(e) => {
+                    warn!("{}, skipping it", e);
+                    None
+                }
+            },
+        )
+        .collect();
+    if found.is_empty() {
+        return Err(format!("None of {} were found", displays.join(", ")));
     }
+    Ok(found)
 }
 
-fn get_window_server_rotation_state(display: &str, backend: &Backend) -> Result<String, String> {
+/// Rotates `display` to `orient` through `backend`, along with the touch
+/// input and the integrated keyboard. Returns the touchscreen matrix if it
+/// could not be set yet, e.g. because the touchscreen isn't plugged in.
+fn rotate(
+    backend: &Backend,
+    display: &str,
+    orient: &Orientation,
+    inputs: &RotatedInputs,
+    dry_run: bool,
+) -> Option<[f32; 9]> {
+    let integrated_keyboard_state = if inputs.keyboard_enabled {
+        "enabled"
+    } else {
+        "disabled"
+    };
+
     match backend {
         Backend::Sway => {
-            let raw_rotation_state = String::from_utf8(
-                Command::new("swaymsg")
-                    .arg("-t")
-                    .arg("get_outputs")
-                    .arg("--raw")
-                    .output()
-                    .expect("Swaymsg get outputs command failed to start")
-                    .stdout,
-            )
-            .unwrap();
-            let deserialized: Vec<SwayOutput> = serde_json::from_str(&raw_rotation_state)
-                .expect("Unable to deserialize swaymsg JSON output");
-            for output in deserialized {
-                if output.name == display {
-                    return Ok(output.transform);
+            // Sent together in one IPC request
+            let mut commands = vec![format!(
+                "output \"{}\" transform {}",
+                display, orient.new_state
+            )];
+
+            // Touch input follows the transform of the output it
+            // is mapped to
+            for touchscreen in inputs.touchscreens {
+                commands.push(format!(
+                    "input \"{}\" map_to_output \"{}\"",
+                    touchscreen, display
+                ));
+            }
+
+            if let KeyboardMode::Integrated = inputs.keyboard_mode {
+                // Disable integrated keyboard outside keyboard_enabled_states
+                for keyboard in inputs.keyboards {
+                    commands.push(format!(
+                        "input \"{}\" events {}",
+                        keyboard, integrated_keyboard_state
+                    ));
                 }
             }
 
-            return Err(format!(
-                "Unable to determine rotation state: display {} not found in 'swaymsg -t get_outputs'",
-                display
-            )
-                .to_owned());
+            if dry_run {
+                println!("swaymsg '{}'", commands.join("; "));
+            } else if let Err(e) = sway::run_commands(&commands) {
+                error!("{}", e);
+            }
         }
-        Backend::Xorg => {
-            let raw_rotation_state = String::from_utf8(
-                Command::new("xrandr")
-                    .output()
-                    .expect("Xrandr get outputs command failed to start")
-                    .stdout,
-            )
-            .unwrap();
-            let xrandr_output_pattern = regex::Regex::new(format!(
-                r"^{} connected .+? .+? (normal |inverted |left |right )?\(normal left inverted right x axis y axis\) .+$",
-                regex::escape(display),
-            ).as_str()).unwrap();
-            for xrandr_output_line in raw_rotation_state.split("\n") {
-                if !xrandr_output_pattern.is_match(xrandr_output_line) {
-                    continue;
+        Backend::Hyprland => {
+            run(
+                Command::new("hyprctl")
+                    .arg("keyword")
+                    .arg("monitor")
+                    .arg(format!("{},transform,{}", display, orient.wl_transform)),
+                dry_run,
+            );
+
+            // Keep the touchscreen tracking the panel
+            run(
+                Command::new("hyprctl")
+                    .arg("keyword")
+                    .arg("input:touchdevice:transform")
+                    .arg(orient.wl_transform.to_string()),
+                dry_run,
+            );
+
+            if let KeyboardMode::Integrated = inputs.keyboard_mode {
+                // Disable integrated keyboard outside keyboard_enabled_states
+                for keyboard in inputs.keyboards {
+                    run(
+                        Command::new("hyprctl")
+                            .arg("keyword")
+                            .arg(format!("device[{}]:enabled", keyboard))
+                            .arg((integrated_keyboard_state == "enabled").to_string()),
+                        dry_run,
+                    );
                 }
+            }
+        }
+        Backend::Cosmic => match get_cosmic_output(display) {
+            Ok(output) => {
+                run(
+                    Command::new("cosmic-randr")
+                        .arg("mode")
+                        .arg("--transform")
+                        .arg(orient.cosmic_state)
+                        .arg(display)
+                        .arg(output.width)
+                        .arg(output.height),
+                    dry_run,
+                );
+            }
+            Err(e) => error!("{}", e),
+        },
+        Backend::Gnome => {
+            if dry_run {
+                println!(
+                    "Mutter ApplyMonitorsConfig: {} transform {}",
+                    display, orient.wl_transform
+                );
+            } else if let Err(e) = gnome::set_transform(display, orient.wl_transform) {
+                error!("{}", e);
+            }
+        }
+        Backend::KwinWayland => {
+            run(
+                Command::new("kscreen-doctor")
+                    .arg(format!("output.{}.rotation.{}", display, orient.kde_state)),
+                dry_run,
+            );
+        }
+        Backend::Niri => {
+            run(
+                Command::new("niri")
+                    .arg("msg")
+                    .arg("output")
+                    .arg(display)
+                    .arg("transform")
+                    .arg(orient.niri_state),
+                dry_run,
+            );
+        }
+        Backend::Wlroots => {
+            run(
+                Command::new("wlr-randr")
+                    .arg("--output")
+                    .arg(display)
+                    .arg("--transform")
+                    .arg(orient.new_state),
+                dry_run,
+            );
+        }
+        #[cfg(feature = "wayland")]
+        Backend::WlrOutputManagement => {
+            if dry_run {
+                println!(
+                    "wlr-output-management: {} transform {}",
+                    display, orient.wl_transform
+                );
+            } else if let Err(e) = wlr_output::set_transform(display, orient.wl_transform) {
+                error!("{}", e);
+            }
+        }
+        Backend::Xorg if dry_run => {
+            println!("xrandr --output {} --rotate {}", display, orient.x_state);
+            if inputs.touch_matrix {
+                println!(
+                    "xinput set-prop \"{}\" \"Coordinate Transformation Matrix\" {}",
+                    inputs.touchscreen,
+                    transform_matrix(orient.wl_transform)
+                        .map(|value| value.to_string())
+                        .join(" ")
+                );
+            }
+        }
+        Backend::Xorg => {
+            if let Err(e) = x11::set_rotation(display, orient.x_state) {
+                error!("{}", e);
+            }
 
-                let xrandr_output_captures =
-                    xrandr_output_pattern.captures(xrandr_output_line).unwrap();
-                if let Some(transform) = xrandr_output_captures.get(1) {
-                    return Ok(transform.as_str().to_owned());
-                } else {
-                    return Ok("normal".to_owned());
+            if inputs.touch_matrix {
+                let matrix = transform_matrix(orient.wl_transform);
+                if let Err(e) = x11::set_touch_matrix(inputs.touchscreen, &matrix) {
+                    warn!("{}", e);
+                    return Some(matrix);
                 }
             }
+        }
+    }
 
-            return Err(format!(
-                "Unable to determine rotation state: display {} not found in xrandr output",
-                display
-            )
-            .to_owned());
+    None
+}
+
+/// Runs the hook for `new_state`, then `rotate_hook`.
+fn run_hooks(
+    state_hooks: &HashMap<&'static str, String>,
+    rotate_hook: &Option<String>,
+    old_state: &str,
+    new_state: &str,
+    display: &str,
+    backend: &Backend,
+    dry_run: bool,
+) {
+    for hook in state_hooks.get(new_state).into_iter().chain(rotate_hook) {
+        run(
+            Command::new("/bin/sh")
+                .arg("-c")
+                .arg(hook)
+                .env("ROT8_OLD_STATE", old_state)
+                .env("ROT8_NEW_STATE", new_state)
+                .env("ROT8_DISPLAY", display)
+                .env("ROT8_BACKEND", backend.name()),
+            dry_run,
+        );
+    }
+}
+
+/// The settings SIGHUP reloads from the config file.
+struct Settings {
+    interval: Duration,
+    max_interval: Duration,
+    /// The displays to rotate, the first of them along with the inputs
+    displays: Vec<String>,
+    classifier: Classifier,
+    flat_threshold: f32,
+    shock_tolerance: f32,
+    stabilize: Duration,
+    stable_samples: u32,
+    keyboard_mode: KeyboardMode,
+    keyboard_enabled_states: Vec<&'static str>,
+    rotate_hook: Option<String>,
+    state_hooks: HashMap<&'static str, String>,
+    hook_debounce: Duration,
+    /// The app_ids whose fullscreen windows hold rotation back, any of them
+    /// when empty, or `None` to rotate regardless
+    inhibit_fullscreen: Option<Vec<String>>,
+}
+
+impl Settings {
+    /// Parses the settings from the command line, falling back to `config`.
+    /// `human_normal` is where the keyboard stays enabled by default.
+    fn parse(
+        matches: &ArgMatches,
+        config: &Config,
+        human_normal: &'static str,
+    ) -> Result<Self, String> {
+        let interval = parse_duration(&arg_value(matches, "sleep", config.sleep.clone()).unwrap())
+            .filter(|interval| !interval.is_zero())
+            .ok_or("--sleep must be a positive duration, e.g. 500 or 500ms, or 2s")?;
+        let max_interval = match arg_value(matches, "max_sleep", config.max_sleep.clone()) {
+            Some(max_sleep) => parse_duration(&max_sleep)
+                .filter(|max_interval| *max_interval >= interval)
+                .ok_or("--max-sleep must be a duration no less than --sleep")?,
+            None => interval,
+        };
+        let displays: Vec<String> = arg_value(matches, "display", config.display.clone())
+            .unwrap()
+            .split(',')
+            .map(|display| display.trim().to_owned())
+            .filter(|display| !display.is_empty())
+            .collect();
+        if displays.is_empty() {
+            return Err("--display must name at least one display".to_owned());
         }
+        let threshold = arg_value(
+            matches,
+            "threshold",
+            config.threshold.map(|t| t.to_string()),
+        )
+        .unwrap()
+        .parse::<f32>()
+        .ok()
+        .filter(|threshold| *threshold > 0.0 && *threshold <= 1.0)
+        .ok_or("--threshold must be a number between 0 and 1")?;
+        let stabilize = arg_value(
+            matches,
+            "stabilize_ms",
+            config.stabilize_ms.map(|s| s.to_string()),
+        )
+        .unwrap()
+        .parse::<u64>()
+        .map(Duration::from_millis)
+        .map_err(|_| "--stabilize-ms must be a number of milliseconds")?;
+        let stable_samples = arg_value(
+            matches,
+            "stable_samples",
+            config.stable_samples.map(|s| s.to_string()),
+        )
+        .unwrap()
+        .parse::<u32>()
+        .ok()
+        .filter(|samples| *samples > 0)
+        .ok_or("--stable-samples must be a positive number of samples")?;
+        let keyboard_mode =
+            match arg_value(matches, "keyboard_mode", config.keyboard_mode.clone()).as_deref() {
+                Some("integrated") => KeyboardMode::Integrated,
+                Some("detachable") => KeyboardMode::Detachable,
+                Some("none") => KeyboardMode::None,
+                _ => {
+                    return Err(
+                        "--keyboard-mode can be one of 'integrated', 'detachable', and 'none'"
+                            .to_owned(),
+                    )
+                }
+            };
+        let keyboard_enabled_states = match arg_value(
+            matches,
+            "keyboard_enabled_states",
+            config.keyboard_enabled_states.clone(),
+        ) {
+            Some(states) => states
+                .split(',')
+                .map(|state| parse_state(state.trim()))
+                .collect::<Result<Vec<_>, _>>()?,
+            None => vec![human_normal],
+        };
+
+        let rotate_hook = arg_value(matches, "rotate_hook", config.rotate_hook.clone());
+        let state_hooks: HashMap<&'static str, String> = vec![
+            (
+                "normal",
+                arg_value(matches, "hook_normal", config.hook_normal.clone()),
+            ),
+            ("90", arg_value(matches, "hook_90", config.hook_90.clone())),
+            (
+                "180",
+                arg_value(matches, "hook_180", config.hook_180.clone()),
+            ),
+            (
+                "270",
+                arg_value(matches, "hook_270", config.hook_270.clone()),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(state, hook)| hook.map(|hook| (state, hook)))
+        .collect();
+        let hook_debounce = arg_value(
+            matches,
+            "hook_debounce_ms",
+            config.hook_debounce_ms.map(|s| s.to_string()),
+        )
+        .unwrap()
+        .parse::<u64>()
+        .map(Duration::from_millis)
+        .map_err(|_| "--hook-debounce-ms must be a number of milliseconds")?;
+        let release_threshold = match arg_value(
+            matches,
+            "release_threshold",
+            config.release_threshold.map(|t| t.to_string()),
+        ) {
+            Some(release_threshold) => release_threshold
+                .parse::<f32>()
+                .ok()
+                .filter(|release_threshold| *release_threshold > 0.0 && *release_threshold <= 1.0)
+                .ok_or("--release-threshold must be a number between 0 and 1")?,
+            None => default_release_threshold(threshold),
+        };
+        let classifier = match arg_value(matches, "classifier", config.classifier.clone())
+            .as_deref()
+        {
+            Some("distance") => Classifier::Distance {
+                threshold,
+                release_threshold,
+            },
+            Some("angle") => Classifier::Angle {
+                deadzone: arg_value(matches, "deadzone", config.deadzone.map(|d| d.to_string()))
+                    .unwrap()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|deadzone| (0.0..45.0).contains(deadzone))
+                    .ok_or("--deadzone must be a number of degrees below 45")?,
+            },
+            _ => return Err("--classifier can be one of 'distance' and 'angle'".to_owned()),
+        };
+        let flat_threshold = arg_value(
+            matches,
+            "flat_threshold",
+            config.flat_threshold.map(|t| t.to_string()),
+        )
+        .unwrap()
+        .parse::<f32>()
+        .map_err(|_| "--flat-threshold must be a number")?;
+        let shock_tolerance = arg_value(
+            matches,
+            "shock_tolerance",
+            config.shock_tolerance.map(|t| t.to_string()),
+        )
+        .unwrap()
+        .parse::<f32>()
+        .map_err(|_| "--shock-tolerance must be a number")?;
+
+        let inhibit_fullscreen =
+            match arg_value(matches, "inhibit_app_id", config.inhibit_app_id.clone()) {
+                Some(app_ids) => Some(
+                    app_ids
+                        .split(',')
+                        .map(|app_id| app_id.trim().to_owned())
+                        .filter(|app_id| !app_id.is_empty())
+                        .collect(),
+                ),
+                None if matches.is_present("inhibit_when_fullscreen")
+                    || config.inhibit_when_fullscreen.unwrap_or(false) =>
+                {
+                    Some(vec![])
+                }
+                None => None,
+            };
+
+        Ok(Settings {
+            interval,
+            max_interval,
+            displays,
+            classifier,
+            flat_threshold,
+            shock_tolerance,
+            stabilize,
+            stable_samples,
+            keyboard_mode,
+            keyboard_enabled_states,
+            rotate_hook,
+            state_hooks,
+            hook_debounce,
+            inhibit_fullscreen,
+        })
     }
 }
 
-fn get_scale() -> Option<f32> {
-    match glob("/sys/bus/iio/devices/iio:device*/in_accel_scale") {
-        Ok(mut paths) => {
-            let path = paths.next()?.ok()?;
-            let scale_raw = fs::read_to_string(path).ok()?;
-            let scale = scale_raw.trim_end_matches('\n').parse::<f32>().ok()?;
-            Some(scale)
+/// Whether a fullscreen window, with one of `app_ids` unless that is empty,
+/// holds rotation back.
+fn fullscreen_inhibits(backend: &Backend, app_ids: &[String]) -> bool {
+    match get_fullscreen_apps(backend) {
+        Ok(apps) => apps
+            .iter()
+            .any(|app| app_ids.is_empty() || app_ids.contains(app)),
+        Err(e) => {
+            warn!("{}", e);
+            false
         }
-        Err(_) => None,
     }
 }
 
-#[derive(Debug)]
-struct Orientation {
-    vector: (f32, f32),
-    new_state: &'static str,
-    x_state: &'static str,
-    matrix: [&'static str; 9],
+/// Whether the screen locker is up, as logind's `LockedHint` says, or as a
+/// running swaylock says, since it doesn't tell logind.
+fn session_locked(session: Option<&logind::Session>) -> bool {
+    let hinted = session.is_some_and(|session| {
+        session.locked().unwrap_or_else(|e| {
+            warn!("{}", e);
+            false
+        })
+    });
+    hinted || (command_available("pidof") && process_running("swaylock"))
 }
 
-fn main() -> Result<(), String> {
-    let mut new_state: &str;
-    let mut x_state: &str;
+/// What holds a rotation back besides the rotation lock, if anything. Only
+/// asked when about to rotate, and the rotation stays pending until nothing
+/// does.
+fn rotation_inhibitor(
+    backend: &Backend,
+    session: Option<&logind::Session>,
+    inhibit_fullscreen: Option<&[String]>,
+) -> Option<&'static str> {
+    if session_locked(session) {
+        // Or the display ends up sideways underneath the locker
+        Some("the session is locked")
+    } else if inhibit_fullscreen.is_some_and(|app_ids| fullscreen_inhibits(backend, app_ids)) {
+        Some("a fullscreen window is on screen")
+    } else {
+        None
+    }
+}
 
-    let mut path_x: String = "".to_string();
-    let mut path_y: String = "".to_string();
-    let mut matrix: [&str; 9];
+/// Prints a line for a waybar custom module with `return-type` json, right
+/// away since waybar reads them as they come.
+fn print_waybar(display: &str, state: &str, locked: bool) {
+    let alt = match state {
+        "normal" => "landscape",
+        "90" => "portrait",
+        "180" => "landscape-inverted",
+        "270" => "portrait-inverted",
+        _ => "unknown",
+    };
+    let lock = if locked { "locked" } else { "unlocked" };
+    println!(
+        "{}",
+        serde_json::json!({
+            "text": state,
+            "alt": alt,
+            "class": [lock, alt],
+            "tooltip": format!("{} is rotated to {}, rotation is {}", display, state, lock),
+        })
+    );
+    let _ = io::stdout().flush();
+}
 
-    let backend = if String::from_utf8(Command::new("pidof").arg("sway").output().unwrap().stdout)
-        .unwrap()
-        .len()
-        >= 1
-    {
-        Backend::Sway
-    } else if String::from_utf8(Command::new("pidof").arg("Xorg").output().unwrap().stdout)
-        .unwrap()
-        .len()
-        >= 1
-    {
-        Backend::Xorg
+/// A file holding rot8's PID, removed again when dropped.
+struct PidFile(PathBuf);
+
+impl PidFile {
+    fn create(path: PathBuf) -> Result<Self, String> {
+        fs::write(&path, format!("{}\n", process::id()))
+            .map_err(|e| format!("Unable to write pidfile {}: {}", path.display(), e))?;
+        Ok(PidFile(path))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Reads a single sample and maps it to the orientation to apply, or
+/// `unknown` when none matches. Also returns the distance to the nearest
+/// orientation.
+fn read_orientation(
+    sensor: &mut Sensor,
+    axes: &AxisRemap,
+    orientations: &[Orientation],
+    classifier: &Classifier,
+    orientation_map: &HashMap<&str, &'static str>,
+    interval: Duration,
+) -> Result<(Sample, &'static str, f32), String> {
+    let sample = axes.apply(sensor.read(interval)?);
+    let (nearest, distance) = nearest_orientation(orientations, sample.vector);
+    let state = if classifier.accepts(nearest, sample.vector) {
+        orientation_map[nearest.new_state]
     } else {
-        return Err("Unable to find Sway or Xorg procceses".to_owned());
+        "unknown"
+    };
+    Ok((sample, state, distance))
+}
+
+/// Picks the orientation for `vector`: the nearest one if `classifier`
+/// accepts it, and `current` otherwise. Also returns whether `vector` matched
+/// an orientation at all.
+///
+/// The current orientation sticks until the vector has moved past the
+/// release threshold, or out of the deadzone, so readings on the boundary
+/// don't flap.
+fn select_orientation<'a>(
+    orientations: &'a [Orientation],
+    current: &'a Orientation,
+    vector: (f32, f32),
+    classifier: &Classifier,
+) -> (&'a Orientation, bool) {
+    let (nearest, _) = nearest_orientation(orientations, vector);
+    let released = match *classifier {
+        _ if nearest.new_state == current.new_state => true,
+        Classifier::Distance {
+            release_threshold, ..
+        } => distance(current, vector) > release_threshold,
+        Classifier::Angle { .. } => true,
     };
 
+    if released && classifier.accepts(nearest, vector) {
+        (nearest, true)
+    } else {
+        (current, false)
+    }
+}
+
+fn main() -> Result<(), String> {
+    let mut backends = vec![
+        "auto",
+        "sway",
+        "hyprland",
+        "cosmic",
+        "niri",
+        "gnome",
+        "kde",
+        "wlroots",
+        "wlr-randr",
+        "xorg",
+        "x11",
+    ];
+    if cfg!(feature = "wayland") {
+        backends.push("wlr-output-management");
+    }
+
     let args = vec![
+        Arg::with_name("config")
+            .long("config")
+            .short("c")
+            .value_name("CONFIG")
+            .help("Set config file path (default: $XDG_CONFIG_HOME/rot8/config.toml)")
+            .takes_value(true),
+        Arg::with_name("backend")
+            .default_value("auto")
+            .long("backend")
+            .short("b")
+            .value_name("BACKEND")
+            .possible_values(&backends)
+            .help("Set the window server backend instead of detecting it")
+            .takes_value(true),
         Arg::with_name("sleep")
             .default_value("500")
             .long("sleep")
             .short("s")
             .value_name("SLEEP")
-            .help("Set sleep millis")
+            .help("Set the time to sleep between samples, in millis or e.g. 500ms or 2s")
+            .takes_value(true),
+        Arg::with_name("max_sleep")
+            .long("max-sleep")
+            .value_name("MAX_SLEEP")
+            .help(
+                "Back off polling up to MAX_SLEEP, in millis or e.g. 5s, while the device is still, going back \
+                to SLEEP as soon as it moves (default: SLEEP, i.e. a fixed interval)"
+            )
+            .takes_value(true),
+        Arg::with_name("set_sampling_frequency")
+            .long("set-sampling-frequency")
+            .help(
+                "Set the accelerometer's sampling frequency to the available one closest to once \
+                every SLEEP millis, restoring it on exit"
+            )
+            .takes_value(false),
+        Arg::with_name("poll_mode")
+            .default_value("interval")
+            .long("poll-mode")
+            .value_name("POLL_MODE")
+            .possible_values(&["interval", "events"])
+            .help(
+                "'interval' - Read the accelerometer every SLEEP millis.\n\
+                'events' - Block on new samples from the accelerometer's IIO buffer"
+            )
+            .takes_value(true),
+        Arg::with_name("sensor")
+            .default_value("auto")
+            .long("sensor")
+            .value_name("SENSOR")
+            .possible_values(&["auto", "sysfs", "evdev", "proxy"])
+            .help(
+                "'sysfs' - Read the IIO accelerometer directly, from in_accel_*_raw or \
+                in_accel_*_input.\n\
+                'evdev' - Read an accelerometer input device from /dev/input.\n\
+                'proxy' - Get the orientation from iio-sensor-proxy.\n\
+                'auto' - Use sysfs if the accelerometer is readable, evdev if there is an \
+                accelerometer input device, iio-sensor-proxy otherwise"
+            )
             .takes_value(true),
         Arg::with_name("display")
             .default_value("eDP-1")
             .long("display")
             .short("d")
             .value_name("DISPLAY")
-            .help("Set Display Device")
+            .help(
+                "Set the display to rotate, or several separated by commas, e.g. eDP-1,HDMI-A-1, \
+                mapping touch input to the first one"
+            )
+            .takes_value(true),
+        Arg::with_name("accel_device")
+            .long("accel-device")
+            .value_name("ACCEL_DEVICE")
+            .help(
+                "Set the IIO accelerometer device by directory or name, e.g. iio:device0 or \
+                accel_3d (default: first one found)"
+            )
+            .takes_value(true),
+        Arg::with_name("accel_name")
+            .long("accel-name")
+            .value_name("ACCEL_NAME")
+            .conflicts_with("accel_device")
+            .help(
+                "Set the IIO accelerometer by its name attribute, e.g. accel_3d or bmc150_accel, \
+                taking the first one if several share it"
+            )
+            .takes_value(true),
+        Arg::with_name("accel_path")
+            .long("accel-path")
+            .value_name("ACCEL_PATH")
+            .conflicts_with_all(&["accel_device", "accel_name"])
+            .help(
+                "Set the IIO accelerometer by its device directory, for when it is not under \
+                /sys/bus/iio/devices"
+            )
+            .takes_value(true),
+        Arg::with_name("accel_scale")
+            .long("accel-scale")
+            .value_name("ACCEL_SCALE")
+            .help(
+                "Scale raw accelerometer readings by ACCEL_SCALE m/s² per unit when the device \
+                has no in_accel_scale of its own"
+            )
+            .takes_value(true),
+        Arg::with_name("scale")
+            .long("scale")
+            .value_name("SCALE")
+            .help(
+                "Scale raw accelerometer readings by SCALE m/s² per unit, whatever scale the \
+                device reports"
+            )
+            .takes_value(true),
+        Arg::with_name("honor_negative_scale")
+            .long("honor-negative-scale")
+            .help(
+                "Use negative accelerometer scales as they are, instead of their absolute value"
+            )
+            .takes_value(false),
+        Arg::with_name("accel_timeout")
+            .default_value("10")
+            .long("accel-timeout")
+            .value_name("ACCEL_TIMEOUT")
+            .help(
+                "Keep looking for the accelerometer for up to ACCEL_TIMEOUT seconds at startup, \
+                in case its driver is still loading"
+            )
             .takes_value(true),
         Arg::with_name("touchscreen")
             .default_value("ELAN0732:00 04F3:22E1")
             .long("touchscreen")
             .short("i")
             .value_name("TOUCHSCREEN")
-            .help("Set Touchscreen input Device (X11 only)")
+            .help(
+                "Set Touchscreen input Device, by X11 name or sway input identifier \
+                (default on sway: every touch input)"
+            )
+            .takes_value(true),
+        Arg::with_name("allowed_states")
+            .long("allowed-states")
+            .value_name("ALLOWED_STATES")
+            .help(
+                "Only ever rotate to these comma separated orientations, e.g. normal,90,270 to \
+                never turn upside down (default: all of them)"
+            )
             .takes_value(true),
         Arg::with_name("threshold")
             .default_value("0.5")
@@ -221,17 +1209,92 @@ fn main() -> Result<(), String> {
             .value_name("THRESHOLD")
             .help("Set a rotation threshold between 0 and 1")
             .takes_value(true),
-
-        Arg::with_name("keyboard_mode")
-            .default_value("integrated")
+        Arg::with_name("classifier")
+            .default_value("distance")
+            .long("classifier")
+            .value_name("CLASSIFIER")
+            .possible_values(&["distance", "angle"])
+            .help(
+                "'distance' - Match orientations within THRESHOLD of the vector.\n\
+                'angle' - Match orientations by the angle of the vector, outside DEADZONE"
+            )
+            .takes_value(true),
+        Arg::with_name("deadzone")
+            .default_value("15")
+            .long("deadzone")
+            .value_name("DEADZONE")
+            .help(
+                "Degrees on either side of the 45° boundaries between orientations that match \
+                none of them, with --classifier angle"
+            )
+            .takes_value(true),
+        Arg::with_name("release_threshold")
+            .long("release-threshold")
+            .value_name("RELEASE_THRESHOLD")
+            .help(
+                "Only leave the current orientation once it is further away than \
+                RELEASE_THRESHOLD, to stop flapping at the boundary (default: THRESHOLD, \
+                at least 0.7, about 4.5° past the boundary)"
+            )
+            .takes_value(true),
+        Arg::with_name("stabilize_ms")
+            .default_value("0")
+            .long("stabilize-ms")
+            .value_name("STABILIZE_MS")
+            .help("Only rotate once a new orientation has held for STABILIZE_MS millis")
+            .takes_value(true),
+        Arg::with_name("stable_samples")
+            .default_value("1")
+            .long("stable-samples")
+            .value_name("STABLE_SAMPLES")
+            .help(
+                "Only rotate once a new orientation has matched STABLE_SAMPLES samples in a row, \
+                which takes about STABLE_SAMPLES * SLEEP millis in interval mode"
+            )
+            .takes_value(true),
+        Arg::with_name("flat_threshold")
+            .default_value("4")
+            .long("flat-threshold")
+            .value_name("FLAT_THRESHOLD")
+            .help(
+                "Keep the current orientation while the device lies flat, i.e. while z² is \
+                more than FLAT_THRESHOLD times x² + y² (0 to disable)"
+            )
+            .takes_value(true),
+        Arg::with_name("shock_tolerance")
+            .default_value("0")
+            .long("shock-tolerance")
+            .value_name("SHOCK_TOLERANCE")
+            .help(
+                "Ignore samples more than SHOCK_TOLERANCE g off 1g, e.g. 0.3 to only trust \
+                0.7 to 1.3g, while the device is bumped or dropped (0 to disable)"
+            )
+            .takes_value(true),
+        Arg::with_name("smoothing")
+            .default_value("1")
+            .long("smoothing")
+            .value_name("SMOOTHING")
+            .help("Average the last SMOOTHING accelerometer samples before picking an orientation")
+            .takes_value(true),
+
+        Arg::with_name("keyboard_mode")
+            .default_value("integrated")
             .long("keyboard-mode")
             .value_name("KEYBOARD_MODE")
             .help(
-                "'integrated' - The keyboard is an integral part of the device. Disable it when device is rotated (Sway only).\n\
+                "'integrated' - The keyboard is an integral part of the device. Disable it when device is rotated (Sway and Hyprland only).\n\
                 'detachable' - The keyboard is detachable. Lock the rotation when it's attached.\n\
                 'none' - Do not enable/disable keyboard"
             )
             .takes_value(true),
+        Arg::with_name("keyboard_enabled_states")
+            .long("keyboard-enabled-states")
+            .value_name("KEYBOARD_ENABLED_STATES")
+            .help(
+                "Comma separated orientations to keep the integrated keyboard enabled in, \
+                e.g. normal,180 (default: the upright orientation)"
+            )
+            .takes_value(true),
         Arg::with_name("keyboard_device")
             .long("keyboard")
             .value_name("KEYBOARD_DEVICE")
@@ -242,225 +1305,1546 @@ fn main() -> Result<(), String> {
         Arg::with_name("rotate_90")
             .long("rotate-90")
             .value_name("ROTATE_90")
-            .help("[PineTab Hack] Enable if the content is 90 degrees counterclockwise when upright")
+            .help(
+                "[PineTab Hack] Enable if the content is 90 degrees counterclockwise when upright \
+                (same as --swap-xy --invert-y)"
+            )
             .takes_value(false),
+        Arg::with_name("orientation_map")
+            .long("orientation-map")
+            .value_name("ORIENTATION_MAP")
+            .help(
+                "Map detected orientations to the ones to apply, e.g. normal=90,90=180,180=270,270=normal \
+                for an accelerometer mounted a quarter turn off (applied after the axis flags)"
+            )
+            .takes_value(true),
         Arg::with_name("flip_y")
             .long("flip-y")
             .value_name("FLIP_Y")
-            .help("[PineTab Hack] Flip Y axis")
+            .help("[PineTab Hack] Flip Y axis (same as --invert-y)")
+            .takes_value(false),
+        Arg::with_name("swap_xy")
+            .long("swap-xy")
+            .help("Swap the accelerometer's x and y axes, before inverting any")
+            .takes_value(false),
+        Arg::with_name("invert_x")
+            .long("invert-x")
+            .help("Invert the accelerometer's x axis")
+            .takes_value(false),
+        Arg::with_name("invert_y")
+            .long("invert-y")
+            .help("Invert the accelerometer's y axis")
+            .takes_value(false),
+        Arg::with_name("invert_z")
+            .long("invert-z")
+            .help("Invert the accelerometer's z axis")
             .takes_value(false),
 
         Arg::with_name("rotate_hook")
             .long("rotate-hook")
             .value_name("ROTATE_HOOK")
-            .help("A shell command to run after rotation")
+            .help(
+                "A shell command to run after rotation, with $ROT8_OLD_STATE, \
+                $ROT8_NEW_STATE, $ROT8_DISPLAY and $ROT8_BACKEND set"
+            )
+            .takes_value(true),
+        Arg::with_name("hook_normal")
+            .long("hook-normal")
+            .value_name("HOOK_NORMAL")
+            .help("A shell command to run after rotating to normal, like --rotate-hook")
+            .takes_value(true),
+        Arg::with_name("hook_90")
+            .long("hook-90")
+            .value_name("HOOK_90")
+            .help("A shell command to run after rotating to 90, like --rotate-hook")
+            .takes_value(true),
+        Arg::with_name("hook_180")
+            .long("hook-180")
+            .value_name("HOOK_180")
+            .help("A shell command to run after rotating to 180, like --rotate-hook")
+            .takes_value(true),
+        Arg::with_name("hook_270")
+            .long("hook-270")
+            .value_name("HOOK_270")
+            .help("A shell command to run after rotating to 270, like --rotate-hook")
             .takes_value(true),
+        Arg::with_name("hook_debounce_ms")
+            .default_value("0")
+            .long("hook-debounce-ms")
+            .value_name("HOOK_DEBOUNCE_MS")
+            .help(
+                "Only run the hooks once a rotation has held for HOOK_DEBOUNCE_MS millis, and not \
+                at all if the display rotated back in the meantime"
+            )
+            .takes_value(true),
+        Arg::with_name("inhibit_when_fullscreen")
+            .long("inhibit-when-fullscreen")
+            .alias("inhibit-fullscreen")
+            .help("Don't rotate while a fullscreen window is on screen (sway only)")
+            .takes_value(false),
+        Arg::with_name("inhibit_app_id")
+            .long("inhibit-app-id")
+            .value_name("INHIBIT_APP_ID")
+            .help(
+                "Only hold rotation back for fullscreen windows with these comma separated \
+                app_ids or X11 classes, e.g. mpv,steam, implying --inhibit-when-fullscreen"
+            )
+            .takes_value(true),
+        Arg::with_name("verbose")
+            .long("verbose")
+            .short("v")
+            .multiple(true)
+            .help("Log every accelerometer reading (-v), or everything (-vv)")
+            .takes_value(false),
+        Arg::with_name("quiet")
+            .long("quiet")
+            .short("q")
+            .conflicts_with("verbose")
+            .help("Only log warnings and errors")
+            .takes_value(false),
+        Arg::with_name("print_state")
+            .long("print-state")
+            .help("Print the current orientation without rotating anything, and exit")
+            .takes_value(false),
+        Arg::with_name("json")
+            .long("json")
+            .requires("print_state")
+            .help("Print the state as JSON, with the raw and normalized accelerometer vectors")
+            .takes_value(false),
+        Arg::with_name("display_detect")
+            .long("display-detect")
+            .conflicts_with_all(&["print_state", "once"])
+            .help("List the backend's displays and their current rotation, and exit")
+            .takes_value(false),
+        Arg::with_name("pidfile")
+            .long("pidfile")
+            .value_name("PIDFILE")
+            .help("Write rot8's PID to PIDFILE, removing it on exit")
+            .takes_value(true),
+        Arg::with_name("control_socket")
+            .long("control-socket")
+            .value_name("CONTROL_SOCKET")
+            .help(
+                "Listen for status, lock, unlock, toggle-lock and set <STATE> commands on \
+                CONTROL_SOCKET, or not at all if it is 'none' \
+                (default: $XDG_RUNTIME_DIR/rot8.sock)"
+            )
+            .takes_value(true),
+        Arg::with_name("rotation_lock_key")
+            .long("rotation-lock-key")
+            .value_name("ROTATION_LOCK_KEY")
+            .help(
+                "Toggle the rotation lock with the rotation lock key, or follow the rotation \
+                lock switch, of this input device, or the first one that has one with 'auto'"
+            )
+            .takes_value(true),
+        Arg::with_name("lock_file")
+            .long("lock-file")
+            .value_name("LOCK_FILE")
+            .help(
+                "Don't rotate while LOCK_FILE exists, like with SIGUSR1 \
+                (default: $XDG_RUNTIME_DIR/rot8.lock)"
+            )
+            .takes_value(true),
+        Arg::with_name("notify")
+            .long("notify")
+            .help(
+                "Show desktop notifications through notify-send when rotation is locked or \
+                unlocked, and on errors rot8 carries on after"
+            )
+            .takes_value(false),
+        Arg::with_name("waybar")
+            .long("waybar")
+            .help(
+                "Print a line of JSON for a waybar custom module whenever the display rotates or \
+                rotation is locked or unlocked"
+            )
+            .takes_value(false),
+        Arg::with_name("restore_on_exit")
+            .long("restore-on-exit")
+            .help("Rotate the display back to normal when exiting on SIGTERM or SIGINT")
+            .takes_value(false),
+        Arg::with_name("once")
+            .long("once")
+            .alias("oneshot")
+            .help(
+                "Apply the current orientation a single time, averaging SMOOTHING samples, and \
+                exit, failing if no orientation matches"
+            )
+            .takes_value(false),
+        Arg::with_name("dry_run")
+            .long("dry-run")
+            .help("Print the commands rotating would run instead of running them")
+            .takes_value(false),
     ];
 
-    let cmd_lines = App::new("rot8").version("0.1.3").args(&args);
+    let cmd_lines = App::new("rot8")
+        .version("0.1.3")
+        .args(&args)
+        .subcommand(SubCommand::with_name("calibrate").about(
+            "Record the accelerometer in each orientation and print the matching \
+            orientation-map",
+        ))
+        .subcommand(
+            SubCommand::with_name("status")
+                .about(
+                    "Print the backend, display, accelerometer, orientation and lock, from the \
+                    running rot8 if there is one",
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the status as a single line of JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("set")
+                .about(
+                    "Rotate to ORIENTATION and exit, or have the running rot8 rotate there and \
+                    lock rotation",
+                )
+                .arg(
+                    Arg::with_name("orientation")
+                        .value_name("ORIENTATION")
+                        .required(true)
+                        .help("normal, 90, 180 or 270, or normal, right, inverted or left"),
+                ),
+        );
 
     let matches = cmd_lines.get_matches();
 
-    let sleep = matches.value_of("sleep").unwrap_or("default.conf");
-    let display = matches.value_of("display").unwrap_or("default.conf");
-    let touchscreen = matches.value_of("touchscreen").unwrap_or("default.conf");
-    let threshold = matches.value_of("threshold").unwrap_or("default.conf");
-    let old_state_owned = get_window_server_rotation_state(display, &backend)?;
-    let mut old_state = old_state_owned.as_str();
-
-    let keyboard_mode = match matches.value_of("keyboard_mode") {
-        Some("integrated") => KeyboardMode::Integrated,
-        Some("detachable") => KeyboardMode::Detachable,
-        Some("none") => KeyboardMode::None,
-        _ => panic!("--keyboard-mode can be one of 'integrated', 'detachable', and 'none'"),
-    };
+    let config = load_config(matches.value_of("config"))?;
 
-    let keyboards = if matches.is_present("keyboard_device") {
-        vec![String::from(matches.value_of("keyboard_device").unwrap())]
+    let verbosity = match matches.occurrences_of("verbose") {
+        0 => config.verbose.unwrap_or(0),
+        occurrences => occurrences,
+    };
+    let log_level = if matches.is_present("quiet") || config.quiet.unwrap_or(false) {
+        "warn"
     } else {
-        get_keyboards(&backend)?
+        match verbosity {
+            0 => "info",
+            1 => "debug",
+            _ => "trace",
+        }
+    };
+    // $RUST_LOG still takes precedence for finer grained filtering
+    env_logger::Builder::from_env(Env::default().default_filter_or(log_level)).init();
+
+    let touchscreen_set = matches.occurrences_of("touchscreen") > 0 || config.touchscreen.is_some();
+    let touchscreen = arg_value(&matches, "touchscreen", config.touchscreen.clone()).unwrap();
+    let smoothing = arg_value(
+        &matches,
+        "smoothing",
+        config.smoothing.map(|s| s.to_string()),
+    )
+    .unwrap()
+    .parse::<usize>()
+    .ok()
+    .filter(|smoothing| *smoothing > 0)
+    .ok_or("--smoothing must be a positive number of samples")?;
+    let poll_mode = match arg_value(&matches, "poll_mode", config.poll_mode.clone()).as_deref() {
+        Some("interval") => PollMode::Interval,
+        Some("events") => PollMode::Events,
+        _ => return Err("--poll-mode can be one of 'interval' and 'events'".to_owned()),
+    };
+    let touchscreen = touchscreen.as_str();
+
+    let rotate_90 = matches.is_present("rotate_90") || config.rotate_90.unwrap_or(false);
+    let flip_y = matches.is_present("flip_y") || config.flip_y.unwrap_or(false);
+    let flag = |name: &str, config_value: Option<bool>| {
+        matches.is_present(name) || config_value.unwrap_or(false)
     };
+    let axes = pinetab_axes(
+        AxisRemap {
+            swap_xy: flag("swap_xy", config.swap_xy),
+            invert_x: flag("invert_x", config.invert_x),
+            invert_y: flag("invert_y", config.invert_y),
+            invert_z: flag("invert_z", config.invert_z),
+        },
+        rotate_90,
+        flip_y,
+    );
+    let explicit_map = match arg_value(&matches, "orientation_map", config.orientation_map.clone())
+    {
+        Some(spec) => parse_orientation_map(&spec)?,
+        None => HashMap::new(),
+    };
+    let orientation_map: HashMap<&str, &str> = STATES
+        .iter()
+        .map(|state| (*state, explicit_map.get(state).copied().unwrap_or(state)))
+        .collect();
+    let orientations = orientations();
 
-    // PineTab Hack
-    let rotate_90 = matches.is_present("rotate_90");
-    let flip_y = matches.is_present("flip_y");
-
-    let rotate_hook = matches.value_of("rotate_hook");
-
-    let scale = get_scale();
-
-    for entry in glob("/sys/bus/iio/devices/iio:device*/in_accel_*_raw").unwrap() {
-        match entry {
-            Ok(path) => {
-                if path.to_str().unwrap().contains("x_raw") {
-                    path_x = path.to_str().unwrap().to_owned();
-                } else if path.to_str().unwrap().contains("y_raw") {
-                    path_y = path.to_str().unwrap().to_owned();
-                } else if path.to_str().unwrap().contains("z_raw") {
-                    continue;
-                } else {
-                    panic!("Unknown accelerometer device path {:?}", path);
+    let human_normal = human_normal(rotate_90, &orientation_map);
+    // Orientations that would rotate to a state that isn't allowed are never
+    // picked, so the nearest allowed one wins instead
+    let allowed_states = match arg_value(&matches, "allowed_states", config.allowed_states.clone())
+    {
+        Some(states) => states
+            .split(',')
+            .map(|state| parse_state(state.trim()))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => STATES.to_vec(),
+    };
+    let allowed_orientations: Vec<Orientation> = orientations
+        .iter()
+        .filter(|orient| allowed_states.contains(&orientation_map[orient.new_state]))
+        .cloned()
+        .collect();
+    if allowed_orientations.is_empty() {
+        return Err("--allowed-states must allow at least one orientation".to_owned());
+    }
+    let Settings {
+        mut interval,
+        mut max_interval,
+        mut displays,
+        mut classifier,
+        mut flat_threshold,
+        mut shock_tolerance,
+        mut stabilize,
+        mut stable_samples,
+        mut keyboard_mode,
+        mut keyboard_enabled_states,
+        mut rotate_hook,
+        mut state_hooks,
+        mut hook_debounce,
+        mut inhibit_fullscreen,
+    } = Settings::parse(&matches, &config, human_normal)?;
+
+    let once = matches.is_present("once");
+    let pidfile = arg_value(&matches, "pidfile", config.pidfile).map(PathBuf::from);
+    let control_socket = match arg_value(&matches, "control_socket", config.control_socket) {
+        Some(path) if path == "none" => None,
+        Some(path) => Some(PathBuf::from(path)),
+        None => control::runtime_path("rot8.sock"),
+    };
+    let lock_file = arg_value(&matches, "lock_file", config.lock_file)
+        .map(PathBuf::from)
+        .or_else(|| control::runtime_path("rot8.lock"));
+    let waybar = flag("waybar", config.waybar);
+    let restore_on_exit =
+        matches.is_present("restore_on_exit") || config.restore_on_exit.unwrap_or(false);
+    let dry_run = matches.is_present("dry_run");
+    let print_state = matches.is_present("print_state");
+
+    let sensor_kind = arg_value(&matches, "sensor", config.sensor).unwrap();
+    let accel_device = arg_value(&matches, "accel_device", config.accel_device);
+    let accel_name = arg_value(&matches, "accel_name", config.accel_name);
+    let accel_path = arg_value(&matches, "accel_path", config.accel_path).map(PathBuf::from);
+    if let Some(path) = &accel_path {
+        if !path.is_dir() {
+            return Err(format!(
+                "--accel-path {} is not a directory",
+                path.display()
+            ));
+        }
+    }
+    let accel_scale = arg_value(
+        &matches,
+        "accel_scale",
+        config.accel_scale.map(|s| s.to_string()),
+    )
+    .map(|scale| {
+        scale
+            .parse::<f32>()
+            .ok()
+            .filter(|scale| *scale > 0.0 && scale.is_finite())
+    })
+    .map(|scale| scale.ok_or("--accel-scale must be a positive number"))
+    .transpose()?;
+    let accel_timeout = arg_value(
+        &matches,
+        "accel_timeout",
+        config.accel_timeout.map(|s| s.to_string()),
+    )
+    .unwrap()
+    .parse::<u64>()
+    .map(Duration::from_secs)
+    .map_err(|_| "--accel-timeout must be a number of seconds")?;
+
+    let honor_negative_scale = flag("honor_negative_scale", config.honor_negative_scale);
+    let scale = arg_value(&matches, "scale", config.scale.map(|s| s.to_string()))
+        .map(|scale| {
+            scale
+                .parse::<f32>()
+                .ok()
+                .filter(|scale| {
+                    scale.is_finite() && (*scale > 0.0 || (honor_negative_scale && *scale < 0.0))
+                })
+                .ok_or("--scale must be a positive number, or negative with --honor-negative-scale")
+        })
+        .transpose()?;
+
+    // Set on SIGTERM and SIGINT, from when the signal handlers are set up
+    let terminate = Arc::new(AtomicBool::new(false));
+    let sensor_options = SensorOptions {
+        kind: sensor_kind,
+        device_name: accel_device,
+        accel_name,
+        accel_path,
+        poll_mode,
+        fallback_scale: accel_scale,
+        scale,
+        honor_negative_scale,
+        sampling_frequency: flag("set_sampling_frequency", config.set_sampling_frequency)
+            .then(|| 1.0 / interval.as_secs_f32()),
+        terminate: Arc::clone(&terminate),
+    };
+
+    let backend_name = arg_value(&matches, "backend", config.backend).unwrap();
+
+    // Needs no accelerometer, to find the --display to pass
+    if matches.is_present("display_detect") {
+        let backend = parse_backend(&backend_name)?;
+        check_backend_tools(&backend)?;
+        for display in get_displays(&backend)? {
+            match get_window_server_rotation_state(&display, &backend) {
+                Ok(state) => println!("{}\t{}", display, state),
+                Err(e) => {
+                    warn!("{}", e);
+                    println!("{}\tunknown", display);
                 }
             }
-            Err(e) => println!("{:?}", e),
         }
+        return Ok(());
     }
 
-    let orientations = [
-        Orientation {
-            vector: (0.0, -1.0),
-            new_state: "normal",
-            x_state: "normal",
-            matrix: ["1", "0", "0", "0", "1", "0", "0", "0", "1"],
-        },
-        Orientation {
-            vector: (0.0, 1.0),
-            new_state: "180",
-            x_state: "inverted",
-            matrix: ["-1", "0", "1", "0", "-1", "1", "0", "0", "1"],
-        },
-        Orientation {
-            vector: (-1.0, 0.0),
-            new_state: "90",
-            x_state: "right",
-            matrix: ["0", "1", "0", "-1", "0", "1", "0", "0", "1"],
-        },
-        Orientation {
-            vector: (1.0, 0.0),
-            new_state: "270",
-            x_state: "left",
-            matrix: ["0", "-1", "1", "1", "0", "0", "0", "0", "1"],
-        },
-    ];
+    let set_orient = match matches
+        .subcommand_matches("set")
+        .and_then(|set| set.value_of("orientation"))
+    {
+        Some(name) => Some(
+            orientations
+                .iter()
+                .find(|orient| orient.new_state == name || orient.x_state == name)
+                .ok_or_else(|| {
+                    format!(
+                        "Unknown orientation '{}', use normal, 90, 180 or 270, or normal, right, \
+                        inverted or left",
+                        name
+                    )
+                })?,
+        ),
+        None => None,
+    };
+    // A running rot8 rotates itself, and stays there rather than rotating
+    // right back
+    if let (Some(orient), Some(path)) = (set_orient, &control_socket) {
+        if !dry_run {
+            if let Ok(reply) = control::send(path, &format!("set {}", orient.new_state)) {
+                return match reply.strip_prefix("error: ") {
+                    Some(e) => Err(e.to_owned()),
+                    None => Ok(()),
+                };
+            }
+        }
+    }
+
+    // Asks a running rot8 for its live state, or reads it once otherwise
+    if let Some(status_matches) = matches.subcommand_matches("status") {
+        let running = control_socket
+            .as_deref()
+            .and_then(|path| control::send(path, "status").ok());
+        let status = match running {
+            Some(reply) => match reply.strip_prefix("error: ") {
+                Some(e) => return Err(e.to_owned()),
+                None => serde_json::from_str(&reply)
+                    .map_err(|e| format!("Unable to parse the running rot8's status: {}", e))?,
+            },
+            None => {
+                let backend = parse_backend(&backend_name)?;
+                check_backend_tools(&backend)?;
+                let mut sensor = Sensor::wait_for(&sensor_options, accel_timeout)?;
+                let (sample, orientation, _) = read_orientation(
+                    &mut sensor,
+                    &axes,
+                    &allowed_orientations,
+                    &classifier,
+                    &orientation_map,
+                    interval,
+                )?;
+                let (display, state) = find_displays(&backend, &displays)?.remove(0);
+                control::Status {
+                    backend: backend.name().to_owned(),
+                    display,
+                    state,
+                    accelerometer: sensor.device().to_owned(),
+                    vector: Some([sample.vector.0, sample.vector.1]),
+                    orientation: orientation.to_owned(),
+                    locked: lock_file.as_ref().is_some_and(|path| path.exists()),
+                    ..Default::default()
+                }
+            }
+        };
 
-    let mut current_orient: &Orientation = &orientations[0];
+        if status_matches.is_present("json") {
+            println!("{}", serde_json::to_string(&status).unwrap());
+        } else {
+            println!("{}", status);
+        }
+        return Ok(());
+    }
 
-    loop {
-        let x_raw = fs::read_to_string(path_x.as_str()).unwrap();
-        let y_raw = fs::read_to_string(path_y.as_str()).unwrap();
-        let x_clean: f32 = x_raw.trim_end_matches('\n').parse::<i32>().unwrap_or(0) as f32;
-        let mut y_clean: f32 = y_raw.trim_end_matches('\n').parse::<i32>().unwrap_or(0) as f32;
+    let calibrating = matches.subcommand_matches("calibrate").is_some();
+    if calibrating || print_state {
+        let mut sensor = Sensor::wait_for(&sensor_options, accel_timeout)?;
+        if calibrating {
+            return calibrate(&mut sensor, &axes, &orientations, interval);
+        }
+
+        let (sample, state, distance) = read_orientation(
+            &mut sensor,
+            &axes,
+            &allowed_orientations,
+            &classifier,
+            &orientation_map,
+            interval,
+        )?;
 
-        let human_normal = if rotate_90 {
-            "90"
+        if matches.is_present("json") {
+            println!(
+                "{}",
+                serde_json::json!({
+                    "raw": [sample.raw.0, sample.raw.1],
+                    "normalized": [sample.vector.0, sample.vector.1],
+                    "state": state,
+                    "distance": distance,
+                })
+            );
         } else {
-            "normal"
+            println!("{}", state);
+        }
+        return Ok(());
+    }
+
+    if flag("notify", config.notify) {
+        if !command_available("notify-send") {
+            warn!("notify-send is not installed, --notify does nothing");
+        }
+        notification::enable();
+    }
+
+    let backend = parse_backend(&backend_name)?;
+    check_backend_tools(&backend)?;
+    if let Backend::Gnome = backend {
+        if gnome::auto_rotation_enabled() {
+            warn!(
+                "GNOME rotates the display by itself while the orientation lock is off, \
+                turn it on to leave rotation to rot8"
+            );
+        }
+    }
+    if inhibit_fullscreen.is_some() && !matches!(backend, Backend::Sway) {
+        warn!(
+            "Only sway tells rot8 about fullscreen windows, --inhibit-when-fullscreen does nothing"
+        );
+    }
+    let session = logind::Session::open()
+        .map_err(|e| debug!("{}, only looking for swaylock", e))
+        .ok();
+    // The first display found stands for all of them in the hooks, and the
+    // others only follow along when it rotates
+    let mut found_displays = find_displays(&backend, &displays)?.into_iter();
+    let (mut display, mut old_state) = found_displays.next().unwrap();
+    let mut other_displays: Vec<(String, String)> = found_displays.collect();
+    let mut reconciled = Instant::now();
+
+    // A keyboard given explicitly stays the only one, otherwise the list is
+    // refreshed as keyboards come and go
+    let keyboard_device = arg_value(&matches, "keyboard_device", config.keyboard);
+    let refresh_keyboards = keyboard_device.is_none();
+    let mut keyboards = match keyboard_device {
+        Some(keyboard) => vec![keyboard],
+        None => get_keyboards(&backend)?,
+    };
+    let mut keyboards_refreshed = Instant::now();
+    // The default touchscreen is an X11 device name, so unless one was given
+    // the backend finds them itself
+    let touchscreens = if touchscreen_set {
+        vec![touchscreen.to_owned()]
+    } else {
+        get_touchscreens(&backend)?
+    };
+    // Whether to set the touchscreen matrix on Xorg. A touchscreen passed
+    // explicitly may just not be plugged in yet, but the default one only
+    // exists on one laptop.
+    let mut touch_matrix = true;
+    if let Backend::Xorg = backend {
+        match x11::input_device_exists(touchscreen) {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!(
+                    "Touchscreen {} not found, pass --touchscreen with its name from \
+                    `xinput list` to rotate touch input",
+                    touchscreen
+                );
+                touch_matrix = touchscreen_set;
+            }
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    if let Some(orient) = set_orient {
+        let inputs = RotatedInputs {
+            touchscreen,
+            touch_matrix,
+            touchscreens: &touchscreens,
+            keyboard_mode: &keyboard_mode,
+            keyboards: &keyboards,
+            keyboard_enabled: keyboard_enabled_states.contains(&orient.new_state),
         };
+        info!("{} -> {}", old_state, orient.new_state);
+        rotate(&backend, &display, orient, &inputs, dry_run);
+        for (other_display, _) in &other_displays {
+            rotate(
+                &backend,
+                other_display,
+                orient,
+                &RotatedInputs::none(),
+                dry_run,
+            );
+        }
+        if old_state != orient.new_state {
+            run_hooks(
+                &state_hooks,
+                &rotate_hook,
+                &old_state,
+                orient.new_state,
+                &display,
+                &backend,
+                dry_run,
+            );
+        }
+        return Ok(());
+    }
 
-        if flip_y {
-            y_clean = -y_clean;
+    // SIGUSR1 toggles the rotation lock, SIGUSR2 looks for keyboards again
+    // and re-applies the current orientation on the next sample, SIGHUP
+    // reloads the config file, and SIGTERM and SIGINT exit after the next
+    // sample, or right away while waiting for the accelerometer. --once
+    // exits by itself, so it listens to none of these, nor to the rotation
+    // lock key, D-Bus or the control socket.
+    let locked = Arc::new(AtomicBool::new(false));
+    let reevaluate = Arc::new(AtomicBool::new(false));
+    let reload = Arc::new(AtomicBool::new(false));
+    if !once {
+        let mut signals = Signals::new([SIGUSR1, SIGUSR2, SIGTERM, SIGINT, SIGHUP])
+            .map_err(|e| format!("Unable to set up signal handlers: {}", e))?;
+        let locked = Arc::clone(&locked);
+        let reevaluate = Arc::clone(&reevaluate);
+        let terminate = Arc::clone(&terminate);
+        let reload = Arc::clone(&reload);
+        thread::spawn(move || {
+            for signal in signals.forever() {
+                match signal {
+                    SIGUSR1 => control::set_locked(&locked, !locked.load(Ordering::SeqCst)),
+                    SIGUSR2 => reevaluate.store(true, Ordering::SeqCst),
+                    SIGHUP => reload.store(true, Ordering::SeqCst),
+                    _ => terminate.store(true, Ordering::SeqCst),
+                }
+            }
+        });
+    }
+    let mut sensor = match Sensor::wait_for(&sensor_options, accel_timeout) {
+        Err(_) if terminate.load(Ordering::SeqCst) => {
+            info!("Exiting");
+            return Ok(());
+        }
+        sensor => sensor?,
+    };
+    // rot8 works without the key, so not being able to read it only warns
+    if let Some(spec) = arg_value(
+        &matches,
+        "rotation_lock_key",
+        config.rotation_lock_key.clone(),
+    )
+    .filter(|_| !once)
+    {
+        if let Err(e) = lock_key::watch(&spec, Arc::clone(&locked)) {
+            warn!("{}", e);
         }
+    }
+    let _pidfile = pidfile.map(PidFile::create).transpose()?;
+
+    let status = Arc::new(Mutex::new(control::Status {
+        backend: backend.name().to_owned(),
+        accelerometer: sensor.device().to_owned(),
+        running: true,
+        ..Default::default()
+    }));
+    let mut rotations = 0;
+    let mut was_file_locked = false;
+    // Whether the last line printed for waybar said rotation was locked
+    let mut waybar_locked: Option<bool> = None;
+    let (request_sender, requests) = mpsc::channel();
+    let dbus_service = if once {
+        None
+    } else {
+        DbusService::start(
+            Arc::clone(&status),
+            Arc::clone(&locked),
+            request_sender.clone(),
+        )
+        .map_err(|e| warn!("{}", e))
+        .ok()
+    };
+    let _control_socket = control_socket.filter(|_| !once).and_then(|path| {
+        control::listen(
+            path,
+            Arc::clone(&status),
+            Arc::clone(&locked),
+            request_sender,
+        )
+        .map_err(|e| warn!("{}", e))
+        .ok()
+    });
+
+    // Start from the orientation the display is in, so lying flat or
+    // matching nothing at startup leaves it alone
+    let mut current_orient: &Orientation = orientations
+        .iter()
+        .find(|orient| orientation_map[orient.new_state] == old_state)
+        .unwrap_or(&orientations[0]);
+    let mut pending: Option<Pending> = None;
+    // The state the hooks rotate from and to, and since when
+    let mut pending_hooks: Option<(String, &'static str, Instant)> = None;
+    let mut pending_matrix: Option<[f32; 9]> = None;
+    let mut smoother = MovingAverage::new(smoothing);
+    let mut samples_read = 0;
+    let mut sleep = interval;
+    let mut last_vector: Option<(f32, f32)> = None;
+
+    #[cfg(feature = "systemd")]
+    let watchdog = systemd::watchdog_enabled();
+    #[cfg(feature = "systemd")]
+    systemd::notify_ready();
 
-        // Normalize vectors
-        let (mut x, mut y): (f32, f32) = match scale {
-            Some(scale) => {
-                (x_clean * scale / 10f32, y_clean * scale / 10f32)
+    loop {
+        #[cfg(feature = "systemd")]
+        if watchdog {
+            systemd::ping_watchdog();
+        }
+
+        // A config that fails to parse leaves the current settings alone
+        if reload.swap(false, Ordering::SeqCst) {
+            match load_config(matches.value_of("config")).and_then(|config| {
+                Settings::parse(&matches, &config, human_normal).map(|settings| (config, settings))
+            }) {
+                Ok((config, settings)) => {
+                    if arg_value(&matches, "backend", config.backend).as_ref()
+                        != Some(&backend_name)
+                    {
+                        warn!("The backend only changes when rot8 restarts, ignoring the new one");
+                    }
+                    let old_displays = displays.clone();
+                    Settings {
+                        interval,
+                        max_interval,
+                        displays,
+                        classifier,
+                        flat_threshold,
+                        shock_tolerance,
+                        stabilize,
+                        stable_samples,
+                        keyboard_mode,
+                        keyboard_enabled_states,
+                        rotate_hook,
+                        state_hooks,
+                        hook_debounce,
+                        inhibit_fullscreen,
+                    } = settings;
+                    sleep = interval;
+                    pending = None;
+                    if displays != old_displays {
+                        match find_displays(&backend, &displays) {
+                            Ok(found) => {
+                                let mut found = found.into_iter();
+                                (display, old_state) = found.next().unwrap();
+                                other_displays = found.collect();
+                            }
+                            Err(e) => warn!("{}, keeping the current displays", e),
+                        }
+                    }
+                    info!("Configuration reloaded");
+                }
+                Err(e) => error!("{}, keeping the current configuration", e),
+            }
+        }
+        let display = display.as_str();
+
+        // Waits up to the interval for the next sample when the sensor blocks
+        let sample = match sensor.read(interval) {
+            Ok(sample) => axes.apply(sample),
+            // Asked to exit while waiting for the accelerometer to come back
+            Err(_) if terminate.load(Ordering::SeqCst) => {
+                info!("Exiting");
+                return Ok(());
             }
-            None => (x_clean / 1f32, y_clean / 1f32),
+            Err(e) => return Err(e),
         };
+        let (x, y) = sample.vector;
 
+        // Poll less and less often while the device is still
+        let moved = last_vector.is_none_or(|(last_x, last_y)| {
+            (x - last_x).powi(2) + (y - last_y).powi(2) > MOTION_DELTA
+        });
+        sleep = if moved {
+            interval
+        } else {
+            (sleep * 2).min(max_interval)
+        };
+        last_vector = Some((x, y));
 
-        // Rotate (HACK)
-        if rotate_90 {
-            // Rotate 90deg clockwise
-            let mx = -x;
-            x = y;
-            y = mx;
+        if let Some(matrix) = pending_matrix {
+            if x11::set_touch_matrix(touchscreen, &matrix).is_ok() {
+                pending_matrix = None;
+            }
         }
 
-        for (_i, orient) in orientations.iter().enumerate() {
-            let d = (x - orient.vector.0).powf(2.0) + (y - orient.vector.1).powf(2.0);
+        // Bumps and drops add to gravity, so the sample says nothing about
+        // how the device is held
+        let shaken = shock_tolerance > 0.0
+            && sample
+                .magnitude
+                .is_some_and(|magnitude| (magnitude - 1.0).abs() > shock_tolerance);
+        if shaken {
+            debug!(
+                "Ignoring a sample of {:.2}g, keeping {}",
+                sample.magnitude.unwrap(),
+                current_orient.new_state
+            );
+            if sensor.polls() {
+                thread::sleep(sleep);
+            }
+            continue;
+        }
 
-            if d < threshold.parse::<f32>().unwrap_or(0.5) {
-                current_orient = &orient;
-                break;
+        // Samples from before the sensor went away say nothing about now
+        if sample.reopened {
+            smoother.reset();
+        }
+        let (x, y) = smoother.push((x, y));
+        debug!("Smoothed vector is ({:.3}, {:.3})", x, y);
+
+        // --once averages a full window before deciding
+        samples_read += 1;
+        if once && samples_read < smoothing {
+            if sensor.polls() {
+                thread::sleep(interval);
             }
+            continue;
         }
 
-        new_state = current_orient.new_state;
-        x_state = current_orient.x_state;
-        matrix = current_orient.matrix;
+        // Pick the closest orientation, as long as it is within the threshold
+        // With gravity mostly on z the x/y vector is just noise
+        let flat = flat_threshold > 0.0
+            && sample
+                .z
+                .is_some_and(|z| z.powi(2) > (x.powi(2) + y.powi(2)) * flat_threshold);
 
-        if new_state != old_state {
-            let integrated_keyboard_state = if new_state == human_normal {
-                "enabled"
+        let matched = if flat {
+            debug!("Lying flat, keeping {}", current_orient.new_state);
+            false
+        } else {
+            // --once has no orientation of its own to hold on to
+            let current = if once {
+                nearest_orientation(&allowed_orientations, (x, y)).0
             } else {
-                "disabled"
+                current_orient
             };
+            let (selected, matched) =
+                select_orientation(&allowed_orientations, current, (x, y), &classifier);
+            current_orient = selected;
+            matched
+        };
+        if once && !matched {
+            return Err(format!(
+                "No orientation matches the accelerometer vector ({:.3}, {:.3})",
+                x, y
+            ));
+        }
+
+        // With --restore-on-exit the display goes back to normal on the way
+        // out, whatever the sensor says
+        let terminating = terminate.load(Ordering::SeqCst);
+        let restoring = terminating && restore_on_exit;
+        // A state set through the control socket is applied as is
+        let requested = requests.try_iter().last();
+        let applied_state = if restoring {
+            "normal"
+        } else if let Some(state) = requested {
+            state
+        } else {
+            orientation_map[current_orient.new_state]
+        };
+        let applied = orientations
+            .iter()
+            .find(|orient| orient.new_state == applied_state)
+            .unwrap();
+
+        let new_state = applied.new_state;
+        debug!(
+            "Nearest orientation is {}, applying {}",
+            current_orient.new_state, new_state
+        );
 
-            println!("{} -> {} (human_normal is {})", old_state, new_state, human_normal);
+        // Something else may have rotated the display in the meantime, and
+        // SIGUSR2 asks to check right away
+        let force = reevaluate.swap(false, Ordering::SeqCst);
+        if !dry_run && (force || reconciled.elapsed() >= RECONCILE_INTERVAL) {
+            reconciled = Instant::now();
+            match get_window_server_rotation_state(display, &backend) {
+                Ok(state) if state != old_state => {
+                    info!("{} was rotated to {} outside of rot8", display, state);
+                    old_state = state;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("{}", e);
+                    notification::error(&e);
+                }
+            }
+            for (other_display, other_state) in &mut other_displays {
+                match get_window_server_rotation_state(other_display, &backend) {
+                    Ok(state) => *other_state = state,
+                    Err(e) => warn!("{}", e),
+                }
+            }
+        }
+
+        let stable = once
+            || debounce(
+                &mut pending,
+                &old_state,
+                new_state,
+                matched,
+                stabilize,
+                stable_samples,
+            );
+
+        if refresh_keyboards
+            && !matches!(keyboard_mode, KeyboardMode::None)
+            && (force || keyboards_refreshed.elapsed() >= KEYBOARD_REFRESH_INTERVAL)
+        {
+            keyboards_refreshed = Instant::now();
+            match get_keyboards(&backend) {
+                Ok(found) => {
+                    if found != keyboards {
+                        debug!("Keyboards are now {:?}", found);
+                    }
+                    keyboards = found;
+                }
+                Err(e) => warn!("{}", e),
+            }
+        }
+        let rotating =
+            force || (new_state != old_state && (stable || restoring || requested.is_some()));
+        let deliberate = restoring || requested.is_some();
+        // Either lock is enough, and once both are gone the orientation left
+        // pending is applied
+        let file_locked = lock_file.as_ref().is_some_and(|path| path.exists());
+        if file_locked != was_file_locked {
+            info!(
+                "Rotation {} by {}",
+                if file_locked { "locked" } else { "unlocked" },
+                lock_file.as_ref().unwrap().display()
+            );
+            notification::lock_changed(file_locked);
+            was_file_locked = file_locked;
+        }
+        let is_locked = file_locked || locked.load(Ordering::SeqCst);
+        if is_locked && !deliberate {
+            if new_state != old_state {
+                debug!("Rotation is locked, staying at {}", old_state);
+            }
+        } else if let Some(inhibitor) = (rotating && !deliberate)
+            .then(|| rotation_inhibitor(&backend, session.as_ref(), inhibit_fullscreen.as_deref()))
+            .flatten()
+        {
+            // Left pending, so it happens once nothing holds it back
+            debug!("Not rotating while {}, staying at {}", inhibitor, old_state);
+        } else if rotating {
+            info!(
+                "{} -> {} (human_normal is {})",
+                old_state, new_state, human_normal
+            );
             let noop = if let KeyboardMode::Detachable = keyboard_mode {
                 // If there are keyboards attached, refuse to rotate to
                 // any orientation but human_normal
-                keyboards_attached(&backend, &keyboards) &&
-                (old_state == human_normal || new_state != human_normal)
+                keyboards_attached(&backend, &keyboards)
+                    && (old_state == human_normal || new_state != human_normal)
             } else {
                 false
             };
 
             if !noop {
-                match backend {
-                    Backend::Sway => {
-                        Command::new("swaymsg")
-                            .arg("output")
-                            .arg(display)
-                            .arg("transform")
-                            .arg(new_state)
-                            .spawn()
-                            .expect("Swaymsg rotate command failed to start")
-                            .wait()
-                            .expect("Swaymsg rotate command wait failed");
-
-                        if let KeyboardMode::Integrated = keyboard_mode {
-                            // Disable integrated keyboard when not human_normal
-                            for keyboard in &keyboards {
-                                Command::new("swaymsg")
-                                    .arg("input")
-                                    .arg(keyboard)
-                                    .arg("events")
-                                    .arg(integrated_keyboard_state)
-                                    .spawn()
-                                    .expect("Swaymsg keyboard command failed to start")
-                                    .wait()
-                                    .expect("Swaymsg keyboard command wait failed");
-                            }
-                        }
-                    }
-                    Backend::Xorg => {
-                        Command::new("xrandr")
-                            .arg("--output")
-                            .arg(display)
-                            .arg("--rotate")
-                            .arg(x_state)
-                            .spawn()
-                            .expect("Xrandr rotate command failed to start")
-                            .wait()
-                            .expect("Xrandr rotate command wait failed");
-
-                        Command::new("xinput")
-                            .arg("set-prop")
-                            .arg(touchscreen)
-                            .arg("Coordinate Transformation Matrix")
-                            .args(&matrix)
-                            .spawn()
-                            .expect("Xinput rotate command failed to start")
-                            .wait()
-                            .expect("Xinput rotate command wait failed");
-
-                    }
+                let inputs = RotatedInputs {
+                    touchscreen,
+                    touch_matrix,
+                    touchscreens: &touchscreens,
+                    keyboard_mode: &keyboard_mode,
+                    keyboards: &keyboards,
+                    keyboard_enabled: keyboard_enabled_states.contains(&new_state),
+                };
+                if let Some(matrix) = rotate(&backend, display, applied, &inputs, dry_run) {
+                    // The touchscreen may not be plugged in yet, keep trying
+                    // in the background
+                    pending_matrix = Some(matrix);
                 }
-                if let Some(hook) = rotate_hook {
-                    Command::new("/bin/sh")
-                        .arg("-c")
-                        .arg(hook)
-                        .spawn()
-                        .expect("Rotate hook command failed to start")
-                        .wait()
-                        .expect("Rotate hook command wait failed");
+                for (other_display, other_state) in &mut other_displays {
+                    if force || *other_state != new_state {
+                        rotate(
+                            &backend,
+                            other_display,
+                            applied,
+                            &RotatedInputs::none(),
+                            dry_run,
+                        );
+                        *other_state = new_state.to_owned();
+                    }
                 }
+
+                // Rotating again before the hooks ran keeps the state they
+                // were going to rotate from
+                let hook_old_state = match pending_hooks.take() {
+                    Some((hook_old_state, _, _)) => hook_old_state,
+                    None => old_state.clone(),
+                };
+                pending_hooks = Some((hook_old_state, new_state, Instant::now()));
+            }
+            if new_state != old_state {
+                rotations += 1;
+            }
+            old_state = new_state.to_owned();
+        }
+
+        // Run the hooks once the orientation has held for the debounce, and
+        // not at all if it went back to where it was
+        if let Some((hook_old_state, hook_new_state, _)) = pending_hooks
+            .take_if(|(_, _, since)| once || terminating || since.elapsed() >= hook_debounce)
+        {
+            if hook_old_state != hook_new_state {
+                run_hooks(
+                    &state_hooks,
+                    &rotate_hook,
+                    &hook_old_state,
+                    hook_new_state,
+                    display,
+                    &backend,
+                    dry_run,
+                );
+            }
+        }
+        // The property getter takes the lock too, so signal after letting go
+        let rotated = {
+            let mut status = status.lock().unwrap();
+            if status.display != display {
+                status.display = display.to_owned();
+            }
+            status.vector = Some([x, y]);
+            status.orientation = if matched { new_state } else { "unknown" }.to_owned();
+            status.rotations = rotations;
+            status.locked = is_locked;
+            let rotated = status.state != old_state;
+            if rotated {
+                status.state = old_state.clone();
+            }
+            rotated
+        };
+        if rotated {
+            if let Some(dbus_service) = &dbus_service {
+                dbus_service.orientation_changed();
+            }
+            #[cfg(feature = "systemd")]
+            systemd::notify_status(&format!("{} is {}", display, old_state));
+        }
+        if waybar && (rotated || waybar_locked != Some(is_locked)) {
+            print_waybar(display, &old_state, is_locked);
+            waybar_locked = Some(is_locked);
+        }
+        if once {
+            return Ok(());
+        }
+        if terminating {
+            info!("Exiting");
+            return Ok(());
+        }
+        if sensor.polls() {
+            thread::sleep(sleep);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The orientation map without --orientation-map
+    fn identity_map() -> HashMap<&'static str, &'static str> {
+        STATES.iter().map(|state| (*state, *state)).collect()
+    }
+
+    #[test]
+    fn keyboard_enabled_upright_with_rotate_90() {
+        // The default --keyboard-enabled-states
+        let keyboard_enabled_states = [human_normal(true, &identity_map())];
+        assert!(keyboard_enabled_states.contains(&"90"));
+        assert!(!keyboard_enabled_states.contains(&"normal"));
+    }
+
+    /// The orientation map for an --orientation-map `spec`
+    fn orientation_map(spec: &str) -> HashMap<&'static str, &'static str> {
+        let explicit_map = parse_orientation_map(spec).unwrap();
+        STATES
+            .iter()
+            .map(|state| (*state, explicit_map.get(state).copied().unwrap_or(state)))
+            .collect()
+    }
+
+    /// The state rot8 picks for the accelerometer reading `raw`
+    fn state_for(
+        raw: (f32, f32),
+        axes: AxisRemap,
+        orientation_map: &HashMap<&str, &'static str>,
+    ) -> &'static str {
+        let orientations = orientations();
+        let (nearest, _) = nearest_orientation(&orientations, axes.apply_vector(raw));
+        orientation_map[nearest.new_state]
+    }
+
+    #[test]
+    fn human_normal_follows_orientation_map() {
+        let orientation_map = orientation_map("normal=270,90=180");
+        assert_eq!(human_normal(false, &orientation_map), "270");
+        assert_eq!(human_normal(true, &orientation_map), "180");
+    }
+
+    #[test]
+    fn human_normal_is_upright_with_flip_y() {
+        // Mounted with y inverted, the sensor reads (0, 1) while upright
+        for spec in [
+            "",
+            "normal=270,90=180",
+            "normal=90,90=normal,180=270,270=180",
+        ] {
+            let orientation_map = orientation_map(spec);
+            for rotate_90 in [false, true] {
+                let axes = pinetab_axes(AxisRemap::default(), rotate_90, true);
+                assert_eq!(
+                    state_for((0.0, 1.0), axes, &orientation_map),
+                    human_normal(rotate_90, &orientation_map),
+                    "--orientation-map {:?}, --rotate-90 {}",
+                    spec,
+                    rotate_90
+                );
             }
-            old_state = new_state;
         }
-        thread::sleep(Duration::from_millis(sleep.parse::<u64>().unwrap_or(0)));
+    }
+
+    #[test]
+    fn mount_matrix_quarter_turn_orientations() {
+        // The chip lies on its side, reading gravity along x while upright
+        let matrix = sensor::parse_mount_matrix("0, 1, 0; -1, 0, 0; 0, 0, 1").unwrap();
+        let orientations = orientations();
+        let classifier = distance_classifier(0.5, default_release_threshold(0.5));
+        // Upright, and turned a quarter so the chip reads gravity along y
+        for (raw, expected) in [((9.8, 0.0), "normal"), ((0.0, 9.8), "270")] {
+            let (x, y, _) = sensor::normalize(sensor::apply_mount_matrix(
+                &matrix,
+                (raw.0, raw.1, Some(0.0)),
+            ));
+            let (nearest, _) = nearest_orientation(&orientations, (x, y));
+            assert_eq!(nearest.new_state, expected, "{:?}", raw);
+            let (selected, matched) =
+                select_orientation(&orientations, &orientations[0], (x, y), &classifier);
+            assert_eq!((selected.new_state, matched), (expected, true), "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn debounce_counts_matching_samples() {
+        let mut pending = None;
+        let mut observe = |new_state, matched| {
+            debounce(
+                &mut pending,
+                "normal",
+                new_state,
+                matched,
+                Duration::ZERO,
+                3,
+            )
+        };
+        assert!(!observe("90", true));
+        assert!(!observe("90", true));
+        assert!(observe("90", true));
+        assert!(observe("90", true));
+    }
+
+    #[test]
+    fn debounce_restarts_on_another_orientation() {
+        let mut pending = None;
+        let mut observe = |new_state, matched| {
+            debounce(
+                &mut pending,
+                "normal",
+                new_state,
+                matched,
+                Duration::ZERO,
+                2,
+            )
+        };
+        assert!(!observe("90", true));
+        assert!(!observe("270", true));
+        assert!(observe("270", true));
+    }
+
+    #[test]
+    fn debounce_restarts_without_a_match() {
+        let mut pending = None;
+        let mut observe = |new_state, matched| {
+            debounce(
+                &mut pending,
+                "normal",
+                new_state,
+                matched,
+                Duration::ZERO,
+                2,
+            )
+        };
+        assert!(!observe("90", true));
+        // Held by hysteresis, but matching no orientation
+        assert!(!observe("90", false));
+        assert!(!observe("90", true));
+        assert!(observe("90", true));
+    }
+
+    #[test]
+    fn debounce_forgets_when_back() {
+        let mut pending = None;
+        assert!(!debounce(
+            &mut pending,
+            "normal",
+            "90",
+            true,
+            Duration::ZERO,
+            2
+        ));
+        assert!(!debounce(
+            &mut pending,
+            "normal",
+            "normal",
+            true,
+            Duration::ZERO,
+            2
+        ));
+        assert!(pending.is_none());
+        assert!(!debounce(
+            &mut pending,
+            "normal",
+            "90",
+            true,
+            Duration::ZERO,
+            2
+        ));
+    }
+
+    #[test]
+    fn debounce_waits_for_stabilize() {
+        let mut pending = None;
+        let stabilize = Duration::from_secs(60);
+        for _ in 0..5 {
+            assert!(!debounce(&mut pending, "normal", "90", true, stabilize, 1));
+        }
+    }
+
+    /// The axis flags, one step at a time: swap, invert x, invert y, then
+    /// flip y and turn a quarter clockwise
+    fn remap_step_by_step(
+        (swap_xy, invert_x, invert_y, flip_y, rotate_90): (bool, bool, bool, bool, bool),
+        (mut x, mut y): (f32, f32),
+    ) -> (f32, f32) {
+        if swap_xy {
+            std::mem::swap(&mut x, &mut y);
+        }
+        if invert_x {
+            x = -x;
+        }
+        if invert_y {
+            y = -y;
+        }
+        if flip_y {
+            y = -y;
+        }
+        if rotate_90 {
+            (x, y) = (y, -x);
+        }
+        (x, y)
+    }
+
+    /// Every combination of --swap-xy, --invert-x, --invert-y, --flip-y and
+    /// --rotate-90
+    fn flag_combinations() -> Vec<(bool, bool, bool, bool, bool)> {
+        (0..32)
+            .map(|bits| {
+                let flag = |bit: u32| bits & (1 << bit) != 0;
+                (flag(0), flag(1), flag(2), flag(3), flag(4))
+            })
+            .collect()
+    }
+
+    fn axes_for(
+        (swap_xy, invert_x, invert_y, flip_y, rotate_90): (bool, bool, bool, bool, bool),
+    ) -> AxisRemap {
+        pinetab_axes(
+            AxisRemap {
+                swap_xy,
+                invert_x,
+                invert_y,
+                invert_z: false,
+            },
+            rotate_90,
+            flip_y,
+        )
+    }
+
+    #[test]
+    fn axis_flags_in_every_combination() {
+        for flags in flag_combinations() {
+            for vector in [(0.25, -1.0), (-0.5, 0.75)] {
+                assert_eq!(
+                    axes_for(flags).apply_vector(vector),
+                    remap_step_by_step(flags, vector),
+                    "--swap-xy, --invert-x, --invert-y, --flip-y, --rotate-90: {:?}",
+                    flags
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn upright_is_human_normal_in_every_combination() {
+        for spec in ["", "normal=270,90=180"] {
+            let orientation_map = orientation_map(spec);
+            for flags in flag_combinations() {
+                let rotate_90 = flags.4;
+                // What a sensor mounted the way the flags correct for reads
+                // while the device is upright
+                let upright = [(0.0, -1.0), (0.0, 1.0), (-1.0, 0.0), (1.0, 0.0)]
+                    .iter()
+                    .copied()
+                    .find(|vector| {
+                        remap_step_by_step(flags, *vector)
+                            == if rotate_90 { (-1.0, 0.0) } else { (0.0, -1.0) }
+                    })
+                    .unwrap();
+                assert_eq!(
+                    state_for(upright, axes_for(flags), &orientation_map),
+                    human_normal(rotate_90, &orientation_map),
+                    "--orientation-map {:?}, --swap-xy, --invert-x, --invert-y, --flip-y, \
+                    --rotate-90: {:?}",
+                    spec,
+                    flags
+                );
+            }
+        }
+    }
+
+    /// The orientation `classifier` matches `vector` to, if any
+    fn classify(classifier: &Classifier, vector: (f32, f32)) -> Option<&'static str> {
+        let orientations = orientations();
+        let (nearest, _) = nearest_orientation(&orientations, vector);
+        Some(nearest.new_state).filter(|_| classifier.accepts(nearest, vector))
+    }
+
+    #[test]
+    fn classifiers_agree() {
+        let distance = Classifier::Distance {
+            threshold: 0.5,
+            release_threshold: 0.5,
+        };
+        let angle = Classifier::Angle { deadzone: 15.0 };
+        let samples = [
+            ((0.0, -1.0), Some("normal")),
+            ((0.0, 1.0), Some("180")),
+            ((-1.0, 0.0), Some("90")),
+            ((1.0, 0.0), Some("270")),
+            ((0.2, -0.95), Some("normal")),
+            ((-0.9, -0.3), Some("90")),
+            ((0.85, 0.4), Some("270")),
+            ((-0.25, 0.9), Some("180")),
+            // On the 45° boundaries
+            ((0.71, -0.71), None),
+            ((-0.71, 0.71), None),
+        ];
+        for (vector, expected) in samples {
+            assert_eq!(
+                classify(&distance, vector),
+                expected,
+                "distance, {:?}",
+                vector
+            );
+            assert_eq!(classify(&angle, vector), expected, "angle, {:?}", vector);
+        }
+    }
+
+    /// The state `select_orientation` picks for `vector`, starting from
+    /// `current`, and whether it matched
+    fn select(current: &str, vector: (f32, f32), classifier: &Classifier) -> (&'static str, bool) {
+        let orientations = orientations();
+        let current = orientations
+            .iter()
+            .find(|orient| orient.new_state == current)
+            .unwrap();
+        let (selected, matched) = select_orientation(&orientations, current, vector, classifier);
+        (selected.new_state, matched)
+    }
+
+    fn distance_classifier(threshold: f32, release_threshold: f32) -> Classifier {
+        Classifier::Distance {
+            threshold,
+            release_threshold,
+        }
+    }
+
+    #[test]
+    fn select_cardinal_orientations() {
+        let classifier = distance_classifier(0.5, 0.5);
+        for (vector, state) in [
+            ((0.0, -1.0), "normal"),
+            ((0.0, 1.0), "180"),
+            ((-1.0, 0.0), "90"),
+            ((1.0, 0.0), "270"),
+        ] {
+            assert_eq!(select("normal", vector, &classifier), (state, true));
+        }
+    }
+
+    #[test]
+    fn select_nearest_not_first() {
+        // Within a loose threshold of both normal and 270, but nearer 270
+        let classifier = distance_classifier(1.9, 0.5);
+        assert_eq!(select("90", (0.8, -0.6), &classifier), ("270", true));
+        assert_eq!(select("90", (0.6, -0.8), &classifier), ("normal", true));
+    }
+
+    #[test]
+    fn select_near_the_boundary() {
+        let classifier = distance_classifier(0.5, 0.5);
+        assert_eq!(
+            select("normal", (0.72, -0.69), &classifier),
+            ("normal", false)
+        );
+        assert_eq!(select("90", (0.72, -0.69), &classifier), ("90", false));
+    }
+
+    #[test]
+    fn select_default_release_threshold() {
+        // Accepts 270 up to about 45.6° away from it
+        let threshold = 0.6;
+        let classifier = distance_classifier(threshold, default_release_threshold(threshold));
+        let towards_270 = |degrees: f32| {
+            let radians = degrees.to_radians();
+            (radians.sin(), -radians.cos())
+        };
+        assert_eq!(
+            select("normal", towards_270(46.0), &classifier),
+            ("normal", false)
+        );
+        assert_eq!(
+            select("normal", towards_270(60.0), &classifier),
+            ("270", true)
+        );
+        // A release threshold no higher than the threshold holds nothing
+        assert_eq!(
+            select(
+                "normal",
+                towards_270(46.0),
+                &distance_classifier(threshold, threshold)
+            ),
+            ("270", true)
+        );
+    }
+
+    #[test]
+    fn select_holds_until_released() {
+        // Nearer 270, and within the threshold of it
+        let vector = (0.8, -0.55);
+        assert_eq!(
+            select("normal", vector, &distance_classifier(0.5, 0.9)),
+            ("normal", false)
+        );
+        assert_eq!(
+            select("normal", vector, &distance_classifier(0.5, 0.5)),
+            ("270", true)
+        );
+    }
+
+    #[test]
+    fn select_by_angle() {
+        let classifier = Classifier::Angle { deadzone: 15.0 };
+        for (degrees, state) in [
+            (-90.0, "normal"),
+            (80.0, "180"),
+            (195.0, "90"),
+            (20.0, "270"),
+        ] {
+            let radians = f32::to_radians(degrees);
+            let vector = (radians.cos(), radians.sin());
+            assert_eq!(select("normal", vector, &classifier), (state, true));
+        }
+    }
+
+    #[test]
+    fn select_rejects_within_the_deadzone() {
+        // 35° from 270, and 10° from the boundary with normal
+        let vector = (35f32.to_radians().cos(), -(35f32.to_radians().sin()));
+        assert_eq!(
+            select("normal", vector, &Classifier::Angle { deadzone: 15.0 }),
+            ("normal", false)
+        );
+        assert_eq!(
+            select("normal", vector, &Classifier::Angle { deadzone: 5.0 }),
+            ("270", true)
+        );
+    }
+
+    #[test]
+    fn touch_matrix_for_every_transform() {
+        #[rustfmt::skip]
+        let matrices = [
+            // normal, 90, 180 and 270
+            [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            [0.0, 1.0, 0.0, -1.0, 0.0, 1.0, 0.0, 0.0, 1.0],
+            [-1.0, 0.0, 1.0, 0.0, -1.0, 1.0, 0.0, 0.0, 1.0],
+            [0.0, -1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0],
+            // The same, flipped
+            [-1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            [0.0, 1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0],
+            [1.0, 0.0, 0.0, 0.0, -1.0, 1.0, 0.0, 0.0, 1.0],
+            [0.0, -1.0, 1.0, -1.0, 0.0, 1.0, 0.0, 0.0, 1.0],
+        ];
+        for (transform, matrix) in matrices.iter().enumerate() {
+            assert_eq!(
+                transform_matrix(transform as u32),
+                *matrix,
+                "transform {}",
+                transform
+            );
+        }
+    }
+
+    #[test]
+    fn touch_matrix_keeps_the_unit_square() {
+        let corners = [(0.0, 0.0), (0.0, 1.0), (1.0, 0.0), (1.0, 1.0)];
+        for transform in 0..8 {
+            let m = transform_matrix(transform);
+            let mut mapped: Vec<(f32, f32)> = corners
+                .iter()
+                .map(|(x, y)| (m[0] * x + m[1] * y + m[2], m[3] * x + m[4] * y + m[5]))
+                .collect();
+            mapped.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert_eq!(mapped, corners, "transform {}", transform);
+        }
     }
 }
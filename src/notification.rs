@@ -0,0 +1,56 @@
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Whether `--notify` was passed.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// The last error shown, so one that keeps happening is only shown once.
+static LAST_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+/// Turns on desktop notifications, which are off by default so rot8 running
+/// as a service doesn't pop any up.
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Shows a notification through `notify-send` in the background, so a slow
+/// notification daemon doesn't hold up rotation.
+fn send(urgency: &'static str, summary: String, body: String) {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return;
+    }
+
+    thread::spawn(move || {
+        match Command::new("notify-send")
+            .arg("--app-name=rot8")
+            .arg(format!("--urgency={}", urgency))
+            .arg(&summary)
+            .arg(&body)
+            .status()
+        {
+            Ok(status) if !status.success() => debug!("notify-send failed with {}", status),
+            Ok(_) => {}
+            Err(e) => debug!("Unable to run notify-send: {}", e),
+        }
+    });
+}
+
+pub fn lock_changed(locked: bool) {
+    send(
+        "low",
+        format!("Rotation {}", if locked { "locked" } else { "unlocked" }),
+        String::new(),
+    );
+}
+
+/// Shows an error rot8 carries on after, unless it is the same as the last
+/// one.
+pub fn error(message: &str) {
+    let mut last_error = LAST_ERROR.lock().unwrap();
+    if last_error.as_deref() == Some(message) {
+        return;
+    }
+    *last_error = Some(message.to_owned());
+    send("normal", "rot8".to_owned(), message.to_owned());
+}
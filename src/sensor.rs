@@ -0,0 +1,1171 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use glob::glob;
+
+use crate::evdev_sensor::EvdevSensor;
+use crate::iio::IioBuffer;
+use crate::notification;
+use crate::sensor_proxy::SensorProxy;
+
+#[derive(Clone, Copy)]
+pub enum PollMode {
+    Interval,
+    Events,
+}
+
+/// Where accelerometer readings come from.
+enum Source {
+    Sysfs {
+        path_x: PathBuf,
+        path_y: PathBuf,
+        path_z: Option<PathBuf>,
+    },
+    Buffer(IioBuffer),
+    Evdev(EvdevSensor),
+    Proxy(SensorProxy),
+}
+
+/// A single accelerometer reading.
+pub struct Sample {
+    /// As read from the sensor
+    pub raw: (f32, f32),
+    /// In units of the gravity vector's length when the sensor has a z
+    /// axis, otherwise scaled so that 1 is roughly 1g
+    pub vector: (f32, f32),
+    /// Scaled like `vector`, if the sensor has a z axis
+    pub z: Option<f32>,
+    /// The length of the acceleration in g, if the sensor has a z axis and a
+    /// scale to tell
+    pub magnitude: Option<f32>,
+    /// Whether the sensor went away and had to be reopened since the
+    /// previous sample
+    pub reopened: bool,
+}
+
+/// Swaps and inverts the accelerometer's axes, for sensors mounted some other
+/// way than rot8 expects. The axes are swapped before being inverted.
+#[derive(Clone, Copy, Default)]
+pub struct AxisRemap {
+    pub swap_xy: bool,
+    pub invert_x: bool,
+    pub invert_y: bool,
+    pub invert_z: bool,
+}
+
+impl AxisRemap {
+    pub fn apply_vector(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let (x, y) = if self.swap_xy { (y, x) } else { (x, y) };
+        let sign = |invert: bool| if invert { -1.0 } else { 1.0 };
+        (x * sign(self.invert_x), y * sign(self.invert_y))
+    }
+
+    /// Remaps the vector and z axis of `sample`, leaving the raw reading as
+    /// it is.
+    pub fn apply(&self, sample: Sample) -> Sample {
+        Sample {
+            vector: self.apply_vector(sample.vector),
+            z: sample.z.map(|z| if self.invert_z { -z } else { z }),
+            ..sample
+        }
+    }
+}
+
+/// An accelerometer, read through whichever interface is available.
+pub struct Sensor {
+    source: Source,
+    calibration: SensorCalibration,
+    mount_matrix: Option<MountMatrix>,
+    /// Restores the device's sampling frequency when dropped
+    _sampling_frequency: Option<SamplingFrequency>,
+    /// What the sensor was opened with, to open it again if it goes away
+    options: SensorOptions,
+    /// Where readings come from, for `rot8 status`
+    device: String,
+}
+
+/// How to find and read the accelerometer.
+#[derive(Clone)]
+pub struct SensorOptions {
+    /// `sysfs`, `evdev`, `proxy` or `auto`
+    pub kind: String,
+    /// The IIO device by directory or `name` attribute
+    pub device_name: Option<String>,
+    /// The IIO device by `name` attribute only
+    pub accel_name: Option<String>,
+    /// The IIO device directory, wherever it is
+    pub accel_path: Option<PathBuf>,
+    pub poll_mode: PollMode,
+    /// The scale to use when the device has none, in m/s² per unit
+    pub fallback_scale: Option<f32>,
+    /// The scale to use whatever the device has, in m/s² per unit
+    pub scale: Option<f32>,
+    /// Use negative scales as they are, instead of taking them for a driver
+    /// quirk
+    pub honor_negative_scale: bool,
+    /// The sampling frequency to set on IIO devices, in Hz
+    pub sampling_frequency: Option<f32>,
+    /// Set once rot8 is asked to exit, to stop waiting for the accelerometer
+    pub terminate: Arc<AtomicBool>,
+}
+
+/// How long to wait before looking for a vanished accelerometer again, at
+/// first and at most
+const REDISCOVER_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_REDISCOVER_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How often to check whether rot8 was asked to exit while waiting
+const TERMINATE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+const TERMINATED: &str = "Asked to exit while waiting for the accelerometer";
+
+/// How many garbled sysfs readings in a row to skip before taking the
+/// sensor for broken and reopening it
+const MAX_INVALID_SAMPLES: u32 = 10;
+
+/// How the accelerometer sits in the chassis, applied to (x, y, z) samples
+pub type MountMatrix = [[f32; 3]; 3];
+
+const IIO_DEVICES: &str = "/sys/bus/iio/devices";
+
+/// The sysfs file of the `axis` acceleration channel: `in_accel_<axis>_raw`,
+/// or `in_accel_<axis>_input` for drivers that only export readings already
+/// in m/s².
+fn accel_channel(device: &Path, axis: &str) -> Option<PathBuf> {
+    ["raw", "input"]
+        .iter()
+        .map(|kind| device.join(format!("in_accel_{}_{}", axis, kind)))
+        .find(|path| path.is_file())
+}
+
+fn has_accel_channels(device: &Path) -> bool {
+    accel_channel(device, "x").is_some() && accel_channel(device, "y").is_some()
+}
+
+/// Whether the device only has `_input` channels, which need no scaling.
+fn has_input_channels(device: &Path) -> bool {
+    accel_channel(device, "x").is_some_and(|path| path.to_string_lossy().ends_with("_input"))
+}
+
+fn iio_devices() -> Result<Vec<PathBuf>, String> {
+    Ok(glob(&format!("{}/iio:device*", IIO_DEVICES))
+        .map_err(|e| format!("Unable to list IIO devices: {}", e))?
+        .flatten()
+        .collect())
+}
+
+fn device_name(device: &Path) -> String {
+    fs::read_to_string(device.join("name"))
+        .map(|name| name.trim().to_owned())
+        .unwrap_or_default()
+}
+
+/// Lists the accelerometers found, e.g. `iio:device0 (accel_3d)`.
+fn accel_candidates(devices: &[PathBuf]) -> String {
+    let candidates: Vec<String> = devices
+        .iter()
+        .filter(|device| has_accel_channels(device))
+        .map(|device| {
+            format!(
+                "{} ({})",
+                device.file_name().unwrap().to_string_lossy(),
+                device_name(device)
+            )
+        })
+        .collect();
+    if candidates.is_empty() {
+        "none".to_owned()
+    } else {
+        candidates.join(", ")
+    }
+}
+
+/// Finds the IIO device directory of the accelerometer: the device called
+/// `name` if given, either by its directory (e.g. `iio:device0`) or by its
+/// `name` attribute (e.g. `accel_3d`), otherwise the first device with x and
+/// y acceleration channels.
+fn find_accel_by_device(name: Option<&str>) -> Result<PathBuf, String> {
+    let devices = iio_devices()?;
+    match name {
+        Some(name) => {
+            let device = devices
+                .iter()
+                .find(|device| device.file_name().is_some_and(|dir| dir == name))
+                .or_else(|| devices.iter().find(|device| device_name(device) == name))
+                .ok_or_else(|| {
+                    format!(
+                        "Accelerometer device {} not found in {}, accelerometers found: {}",
+                        name,
+                        IIO_DEVICES,
+                        accel_candidates(&devices)
+                    )
+                })?;
+            if !has_accel_channels(device) {
+                return Err(format!(
+                    "IIO device {} has no x and y acceleration channels, accelerometers found: {}",
+                    name,
+                    accel_candidates(&devices)
+                ));
+            }
+            Ok(device.clone())
+        }
+        None => devices
+            .into_iter()
+            .find(|device| has_accel_channels(device))
+            .ok_or_else(|| {
+                format!(
+                    "No accelerometer with in_accel_x and in_accel_y channels found in {}, \
+                    is its driver loaded?",
+                    IIO_DEVICES
+                )
+            }),
+    }
+}
+
+/// Finds the accelerometer whose `name` attribute is `name`, taking the first
+/// one if several share it.
+fn find_accel_by_name(name: &str) -> Result<PathBuf, String> {
+    let devices = iio_devices()?;
+    let mut matching = devices
+        .iter()
+        .filter(|device| has_accel_channels(device) && device_name(device) == name);
+    let device = matching.next().ok_or_else(|| {
+        format!(
+            "No accelerometer called {} found in {}, accelerometers found: {}",
+            name,
+            IIO_DEVICES,
+            accel_candidates(&devices)
+        )
+    })?;
+    if matching.next().is_some() {
+        info!(
+            "Several accelerometers are called {}, using {}",
+            name,
+            device.display()
+        );
+    }
+    Ok(device.clone())
+}
+
+/// Checks that `path` is an accelerometer's device directory, for setups
+/// where it is not under `/sys/bus/iio/devices`.
+fn find_accel_by_path(path: &Path) -> Result<PathBuf, String> {
+    if has_accel_channels(path) {
+        return Ok(path.to_owned());
+    }
+
+    let mut channels: Vec<String> = fs::read_dir(path)
+        .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("in_"))
+        .collect();
+    channels.sort();
+    Err(format!(
+        "{} has no x and y acceleration channels, channels found: {}",
+        path.display(),
+        if channels.is_empty() {
+            "none".to_owned()
+        } else {
+            channels.join(", ")
+        }
+    ))
+}
+
+/// Finds the accelerometer `options` select, by path or name if given.
+fn find_accel_device(options: &SensorOptions) -> Result<PathBuf, String> {
+    match (&options.accel_path, &options.accel_name) {
+        (Some(path), _) => find_accel_by_path(path),
+        (None, Some(name)) => find_accel_by_name(name),
+        (None, None) => find_accel_by_device(options.device_name.as_deref()),
+    }
+}
+
+/// Sleeps for `duration`, or until `terminate` is set. Returns whether it
+/// was.
+fn sleep_unless_terminated(terminate: &AtomicBool, duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    while !terminate.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        thread::sleep((deadline - now).min(TERMINATE_CHECK_INTERVAL));
+    }
+    true
+}
+
+fn read_attribute(device: &Path, name: &str) -> Option<f32> {
+    let raw = fs::read_to_string(device.join(name)).ok()?;
+    raw.trim_end_matches('\n').parse::<f32>().ok()
+}
+
+/// Checks a scale read from the device. Zero (as some drivers briefly report
+/// after resume) is no scale at all, and negative scales, which would mirror
+/// the axes, are taken for their absolute value unless `honor_negative`.
+fn check_scale(scale: f32, honor_negative: bool) -> Option<f32> {
+    if scale == 0.0 || !scale.is_finite() {
+        None
+    } else if scale < 0.0 && !honor_negative {
+        Some(-scale)
+    } else {
+        Some(scale)
+    }
+}
+
+/// How raw IIO readings convert to acceleration: `(raw + offset) * scale`.
+#[derive(Default)]
+struct SensorCalibration {
+    /// x, y and z scales, if the device has any
+    scales: Option<[f32; 3]>,
+    /// x, y and z offsets
+    offsets: [f32; 3],
+    /// The device to read the scales from again, while one of them reads
+    /// zero
+    reread: Option<PathBuf>,
+    /// Where the scales came from, for the log
+    origin: &'static str,
+}
+
+impl SensorCalibration {
+    /// The same scale on every axis and no offsets.
+    fn uniform(scale: f32, origin: &'static str) -> Self {
+        SensorCalibration {
+            scales: Some([scale; 3]),
+            origin,
+            ..Default::default()
+        }
+    }
+
+    fn log(&self) {
+        match self.scales {
+            Some(scales) if scales.iter().all(|scale| *scale == scales[0]) => {
+                info!(
+                    "Using accelerometer scale {} from {}",
+                    scales[0], self.origin
+                )
+            }
+            Some(scales) => info!(
+                "Using accelerometer scales {:?} from {}",
+                scales, self.origin
+            ),
+            None => info!("Accelerometer has no scale, normalizing readings by their length"),
+        }
+    }
+
+    /// Reads the scales and offsets of `device`, using the per-channel files
+    /// where there are any (as HID sensor hubs export) and the shared ones
+    /// otherwise. `options.fallback_scale` stands in for the shared scale if
+    /// the device has none, or it reads zero, and `options.scale` overrides
+    /// whatever the device has.
+    fn read(device: &Path, options: &SensorOptions) -> Self {
+        let shared_offset = read_attribute(device, "in_accel_offset").unwrap_or(0.0);
+        let offsets = ["x", "y", "z"].map(|axis| {
+            read_attribute(device, &format!("in_accel_{}_offset", axis)).unwrap_or(shared_offset)
+        });
+
+        if let Some(scale) = options.scale {
+            return SensorCalibration {
+                offsets,
+                ..SensorCalibration::uniform(scale, "--scale")
+            };
+        }
+
+        let mut zero_scale = false;
+        let mut from_sysfs = false;
+        let mut scale = |name: &str| {
+            let scale = read_attribute(device, name)?;
+            if scale == 0.0 {
+                zero_scale = true;
+            } else if scale < 0.0 && !options.honor_negative_scale {
+                warn!("{} is negative ({}), using its absolute value", name, scale);
+            }
+            let scale = check_scale(scale, options.honor_negative_scale);
+            from_sysfs |= scale.is_some();
+            scale
+        };
+
+        let shared_scale = scale("in_accel_scale").or(options.fallback_scale);
+        let scales =
+            ["x", "y", "z"].map(|axis| scale(&format!("in_accel_{}_scale", axis)).or(shared_scale));
+
+        SensorCalibration {
+            scales: if scales.iter().any(Option::is_some) {
+                Some(scales.map(|scale| scale.unwrap_or(1.0)))
+            } else {
+                None
+            },
+            offsets,
+            reread: if zero_scale {
+                Some(device.to_owned())
+            } else {
+                None
+            },
+            origin: if from_sysfs { "sysfs" } else { "--accel-scale" },
+        }
+    }
+
+    /// Converts raw readings to roughly 1 per g (scales are in m/s²), or
+    /// just applies the offsets when the device has no scale.
+    fn apply(&self, (x, y, z): (f32, f32, Option<f32>)) -> (f32, f32, Option<f32>) {
+        let scales = self
+            .scales
+            .map_or([1f32; 3], |scales| scales.map(|scale| scale / 10f32));
+        (
+            (x + self.offsets[0]) * scales[0],
+            (y + self.offsets[1]) * scales[1],
+            z.map(|z| (z + self.offsets[2]) * scales[2]),
+        )
+    }
+}
+
+/// The entry of `available` closest to `target`, or `target` itself when the
+/// device lists no frequencies.
+fn closest_frequency(available: &[f32], target: f32) -> f32 {
+    available
+        .iter()
+        .copied()
+        .min_by(|a, b| (a - target).abs().total_cmp(&(b - target).abs()))
+        .unwrap_or(target)
+}
+
+/// A sampling frequency set on an IIO device, put back to what it was when
+/// dropped.
+struct SamplingFrequency {
+    path: PathBuf,
+    original: String,
+}
+
+impl SamplingFrequency {
+    /// Sets the sampling frequency of `device` to the available one closest
+    /// to `target` Hz. Failing is not fatal, the device just keeps sampling
+    /// at its own rate.
+    fn set(device: &Path, target: f32) -> Option<Self> {
+        let (path, available) = ["in_accel_sampling_frequency", "sampling_frequency"]
+            .iter()
+            .map(|name| {
+                (
+                    device.join(name),
+                    device.join(format!("{}_available", name)),
+                )
+            })
+            .find(|(path, _)| path.is_file())?;
+        let original = fs::read_to_string(&path).ok()?;
+        // Some drivers list a range rather than values, which parses to none
+        let available: Vec<f32> = fs::read_to_string(available)
+            .unwrap_or_default()
+            .split_whitespace()
+            .filter_map(|value| value.parse().ok())
+            .collect();
+        let frequency = closest_frequency(&available, target);
+
+        match fs::write(&path, frequency.to_string()) {
+            Ok(()) => {
+                info!(
+                    "Set the accelerometer sampling frequency to {} Hz, from {} Hz",
+                    frequency,
+                    original.trim_end()
+                );
+                Some(SamplingFrequency { path, original })
+            }
+            Err(e) => {
+                warn!(
+                    "Unable to set the accelerometer sampling frequency in {}: {}",
+                    path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+}
+
+impl Drop for SamplingFrequency {
+    fn drop(&mut self) {
+        let _ = fs::write(&self.path, self.original.trim_end());
+    }
+}
+
+/// Parses a mount matrix in the `ACCEL_MOUNT_MATRIX` format: three rows
+/// separated by `;`, each with three values separated by `,`.
+pub fn parse_mount_matrix(spec: &str) -> Option<MountMatrix> {
+    let mut matrix = [[0.0; 3]; 3];
+    let rows: Vec<&str> = spec.split(';').collect();
+    if rows.len() != 3 {
+        return None;
+    }
+    for (row, values) in matrix.iter_mut().zip(rows) {
+        let values: Vec<&str> = values.split(',').collect();
+        if values.len() != 3 {
+            return None;
+        }
+        for (value, raw) in row.iter_mut().zip(values) {
+            *value = raw.trim().parse().ok()?;
+        }
+    }
+    Some(matrix)
+}
+
+/// Reads the `ACCEL_MOUNT_MATRIX` udev property that systemd's hwdb sets for
+/// accelerometers known to be mounted rotated.
+fn udev_mount_matrix(device: &Path) -> Option<MountMatrix> {
+    let output = Command::new("udevadm")
+        .arg("info")
+        .arg("--query=property")
+        .arg(format!("--path={}", device.display()))
+        .output()
+        .ok()?;
+    let properties = String::from_utf8(output.stdout).ok()?;
+    let spec = properties
+        .lines()
+        .find_map(|line| line.strip_prefix("ACCEL_MOUNT_MATRIX="))?;
+
+    match parse_mount_matrix(spec) {
+        Some(matrix) => {
+            info!("Applying ACCEL_MOUNT_MATRIX {} from udev", spec);
+            Some(matrix)
+        }
+        None => {
+            warn!("Ignoring invalid ACCEL_MOUNT_MATRIX {}", spec);
+            None
+        }
+    }
+}
+
+/// Reads the mount matrix the IIO driver exports, if any.
+fn sysfs_mount_matrix(device: &Path) -> Option<MountMatrix> {
+    ["in_accel_mount_matrix", "in_mount_matrix"]
+        .iter()
+        .find_map(|name| {
+            let spec = fs::read_to_string(device.join(name)).ok()?;
+            let spec = spec.trim();
+            match parse_mount_matrix(spec) {
+                Some(matrix) => {
+                    info!("Applying {} {} from sysfs", name, spec);
+                    Some(matrix)
+                }
+                None => {
+                    warn!("Ignoring invalid {} {}", name, spec);
+                    None
+                }
+            }
+        })
+}
+
+/// Multiplies an (x, y, z) sample by `matrix`. Samples without a z axis are
+/// treated as having z = 0.
+pub fn apply_mount_matrix(
+    matrix: &MountMatrix,
+    (x, y, z): (f32, f32, Option<f32>),
+) -> (f32, f32, Option<f32>) {
+    let row = |row: &[f32; 3]| row[0] * x + row[1] * y + row[2] * z.unwrap_or(0.0);
+    (row(&matrix[0]), row(&matrix[1]), z.map(|_| row(&matrix[2])))
+}
+
+/// Divides a sample by its length, so that it compares against the unit
+/// vectors of the orientations whatever unit the scale works out to (m/s², g
+/// or none at all). Without a z axis the length of gravity is unknown, and
+/// the sample is left as it is.
+pub fn normalize((x, y, z): (f32, f32, Option<f32>)) -> (f32, f32, Option<f32>) {
+    let z = match z {
+        Some(z) => z,
+        None => return (x, y, None),
+    };
+    // Squaring would overflow with huge scales
+    let magnitude = x.hypot(y).hypot(z);
+    if magnitude == 0.0 {
+        return (x, y, Some(z));
+    }
+    (x / magnitude, y / magnitude, Some(z / magnitude))
+}
+
+/// Why a sysfs channel couldn't be read.
+enum RawError {
+    /// The file is gone or unreadable, as when the device goes away
+    Unreadable(String),
+    /// The file holds something other than a number
+    Invalid(String),
+}
+
+fn read_raw(path: &Path) -> Result<f32, RawError> {
+    let raw = fs::read_to_string(path)
+        .map_err(|e| RawError::Unreadable(format!("Unable to read {}: {}", path.display(), e)))?;
+    let raw = raw.trim_end_matches('\n');
+    // _input channels may hold fractional m/s²
+    raw.parse::<f32>().map_err(|e| {
+        RawError::Invalid(format!(
+            "Invalid reading '{}' in {}: {}",
+            raw,
+            path.display(),
+            e
+        ))
+    })
+}
+
+impl Sensor {
+    /// Opens the accelerometer for `options.kind` (`sysfs`, `evdev`, `proxy`
+    /// or `auto`). IIO devices are read through their buffer in events mode,
+    /// falling back to polling sysfs when the device has no usable buffer.
+    pub fn open(options: &SensorOptions) -> Result<Self, String> {
+        match options.kind.as_str() {
+            "sysfs" => {
+                let device = find_accel_device(options)?;
+                return Ok(Sensor::open_iio(&device, options));
+            }
+            "evdev" => {
+                return EvdevSensor::open()?
+                    .map(|evdev| Sensor::open_evdev(evdev, options))
+                    .ok_or_else(|| "No accelerometer input device found in /dev/input".to_owned());
+            }
+            "proxy" => {}
+            "auto" => {
+                let device = find_accel_device(options).ok().filter(|device| {
+                    accel_channel(device, "x").is_some_and(|path| fs::read_to_string(path).is_ok())
+                });
+                if let Some(device) = device {
+                    return Ok(Sensor::open_iio(&device, options));
+                }
+                match EvdevSensor::open() {
+                    Ok(Some(evdev)) => return Ok(Sensor::open_evdev(evdev, options)),
+                    Ok(None) => {}
+                    Err(e) => warn!("{}", e),
+                }
+            }
+            _ => {
+                return Err(
+                    "--sensor can be one of 'auto', 'sysfs', 'evdev' and 'proxy'".to_owned(),
+                )
+            }
+        }
+
+        Ok(Sensor {
+            // iio-sensor-proxy applies the mount matrix itself
+            source: Source::Proxy(SensorProxy::open()?),
+            calibration: SensorCalibration::default(),
+            mount_matrix: None,
+            _sampling_frequency: None,
+            options: options.clone(),
+            device: "iio-sensor-proxy".to_owned(),
+        })
+    }
+
+    /// Like `open`, but keeps retrying for up to `timeout`, for when rot8
+    /// starts before the accelerometer driver has probed.
+    pub fn wait_for(options: &SensorOptions, timeout: Duration) -> Result<Self, String> {
+        let deadline = Instant::now() + timeout;
+        let mut backoff = REDISCOVER_BACKOFF;
+        loop {
+            let e = match Sensor::open(options) {
+                Ok(sensor) => return Ok(sensor),
+                Err(e) => e,
+            };
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(e);
+            }
+            info!("{}, retrying", e);
+            if sleep_unless_terminated(&options.terminate, backoff.min(deadline - now)) {
+                return Err(TERMINATED.to_owned());
+            }
+            backoff = (backoff * 2).min(MAX_REDISCOVER_BACKOFF);
+        }
+    }
+
+    fn open_evdev(evdev: EvdevSensor, options: &SensorOptions) -> Self {
+        // The resolution is in units per g, the calibration scale in m/s²
+        let calibration = match (options.scale, evdev.resolution(), options.fallback_scale) {
+            (Some(scale), _, _) => SensorCalibration::uniform(scale, "--scale"),
+            (None, Some(resolution), _) => {
+                SensorCalibration::uniform(10.0 / resolution as f32, "the evdev resolution")
+            }
+            (None, None, Some(scale)) => SensorCalibration::uniform(scale, "--accel-scale"),
+            (None, None, None) => SensorCalibration::default(),
+        };
+        calibration.log();
+
+        Sensor {
+            calibration,
+            mount_matrix: None,
+            _sampling_frequency: None,
+            source: Source::Evdev(evdev),
+            options: options.clone(),
+            device: "evdev".to_owned(),
+        }
+    }
+
+    fn open_iio(device: &Path, options: &SensorOptions) -> Self {
+        let sysfs = || Source::Sysfs {
+            path_x: accel_channel(device, "x").unwrap(),
+            path_y: accel_channel(device, "y").unwrap(),
+            path_z: accel_channel(device, "z"),
+        };
+        let source = match options.poll_mode {
+            PollMode::Interval => sysfs(),
+            PollMode::Events => match IioBuffer::open(device) {
+                Ok(buffer) => Source::Buffer(buffer),
+                Err(e) => {
+                    warn!("{}, polling sysfs instead", e);
+                    sysfs()
+                }
+            },
+        };
+
+        let calibration = if options.scale.is_none() && has_input_channels(device) {
+            // Already in m/s²
+            SensorCalibration::uniform(1.0, "the _input channels, already in m/s²")
+        } else {
+            SensorCalibration::read(device, options)
+        };
+        calibration.log();
+        if calibration.reread.is_some() {
+            warn!("Accelerometer scale reads zero, reading it again until it doesn't");
+        }
+
+        Sensor {
+            calibration,
+            // hwdb entries exist to correct what drivers get wrong, so they win
+            mount_matrix: udev_mount_matrix(device).or_else(|| sysfs_mount_matrix(device)),
+            _sampling_frequency: options
+                .sampling_frequency
+                .and_then(|frequency| SamplingFrequency::set(device, frequency)),
+            source,
+            options: options.clone(),
+            device: device.display().to_string(),
+        }
+    }
+
+    /// Waits for the accelerometer to show up again after it went away, e.g.
+    /// because a USB dock was unplugged, and reopens it. Fails only if rot8
+    /// is asked to exit in the meantime.
+    fn reopen(&mut self) -> Result<(), String> {
+        let mut backoff = REDISCOVER_BACKOFF;
+        loop {
+            if sleep_unless_terminated(&self.options.terminate, backoff) {
+                return Err(TERMINATED.to_owned());
+            }
+            if let Source::Evdev(_) = self.source {
+                match EvdevSensor::open() {
+                    Ok(Some(evdev)) => {
+                        info!("Accelerometer input device found again");
+                        *self = Sensor::open_evdev(evdev, &self.options);
+                        return Ok(());
+                    }
+                    Ok(None) => {}
+                    Err(e) => debug!("{}", e),
+                }
+            } else {
+                match find_accel_device(&self.options) {
+                    Ok(device) if has_accel_channels(&device) => {
+                        info!("Accelerometer found again at {}", device.display());
+                        *self = Sensor::open_iio(&device, &self.options);
+                        return Ok(());
+                    }
+                    Ok(_) => {}
+                    Err(e) => debug!("{}", e),
+                }
+            }
+            backoff = (backoff * 2).min(MAX_REDISCOVER_BACKOFF);
+        }
+    }
+
+    /// The IIO device directory, or `evdev` or `iio-sensor-proxy`.
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    /// Whether the sensor has to be polled, as opposed to blocking until
+    /// there is something new to read.
+    pub fn polls(&self) -> bool {
+        matches!(self.source, Source::Sysfs { .. } | Source::Evdev(_))
+    }
+
+    /// Reads the next sample. Waits for at most `timeout` when the sensor
+    /// blocks until there is something new, going with the previous reading
+    /// if nothing comes.
+    pub fn read(&mut self, timeout: Duration) -> Result<Sample, String> {
+        let mut reopened = false;
+        let mut invalid = 0;
+        let (x_raw, y_raw, z_raw) = loop {
+            let reading = match self.source {
+                Source::Buffer(ref mut buffer) => match buffer.read(timeout) {
+                    Ok(Some((x_raw, y_raw, z_raw))) => {
+                        Ok((x_raw as f32, y_raw as f32, z_raw.map(|z_raw| z_raw as f32)))
+                    }
+                    // No sample yet to fall back on
+                    Ok(None) if self.options.terminate.load(Ordering::SeqCst) => {
+                        return Err(TERMINATED.to_owned())
+                    }
+                    Ok(None) => continue,
+                    Err(e) => Err(e),
+                },
+                Source::Evdev(ref evdev) => evdev.read().map(|(x_raw, y_raw, z_raw)| {
+                    (x_raw as f32, y_raw as f32, z_raw.map(|z_raw| z_raw as f32))
+                }),
+                // Losing iio-sensor-proxy is not something to wait out
+                Source::Proxy(ref mut proxy) => {
+                    let (x, y) = proxy.read(timeout)?;
+                    Ok((x, y, None))
+                }
+                Source::Sysfs {
+                    ref path_x,
+                    ref path_y,
+                    ref path_z,
+                } => match (|| {
+                    Ok((
+                        read_raw(path_x)?,
+                        read_raw(path_y)?,
+                        path_z.as_deref().map(read_raw).transpose()?,
+                    ))
+                })() {
+                    Ok(reading) => Ok(reading),
+                    Err(RawError::Unreadable(e)) => Err(e),
+                    // A garbled reading is no reason to think the sensor is
+                    // gone, unless it keeps coming
+                    Err(RawError::Invalid(e)) if invalid + 1 < MAX_INVALID_SAMPLES => {
+                        invalid += 1;
+                        warn!("{}, skipping the sample", e);
+                        if sleep_unless_terminated(&self.options.terminate, timeout) {
+                            return Err(TERMINATED.to_owned());
+                        }
+                        continue;
+                    }
+                    Err(RawError::Invalid(e)) => {
+                        invalid = 0;
+                        Err(format!("{}, {} times in a row", e, MAX_INVALID_SAMPLES))
+                    }
+                },
+            };
+
+            match reading {
+                Ok(reading) => break reading,
+                Err(e) => {
+                    let message = format!("{}, waiting for the accelerometer to come back", e);
+                    warn!("{}", message);
+                    notification::error(&message);
+                    self.reopen()?;
+                    reopened = true;
+                }
+            }
+        };
+
+        if let Some(device) = self.calibration.reread.take() {
+            self.calibration = SensorCalibration::read(&device, &self.options);
+            if self.calibration.reread.is_none() {
+                info!("Accelerometer scale no longer reads zero");
+            }
+        }
+
+        let calibrated = self.calibration.apply((x_raw, y_raw, z_raw));
+        let mounted = match &self.mount_matrix {
+            Some(matrix) => apply_mount_matrix(matrix, calibrated),
+            None => calibrated,
+        };
+        let magnitude = match mounted {
+            (x, y, Some(z)) if self.calibration.scales.is_some() => {
+                Some((x * x + y * y + z * z).sqrt())
+            }
+            _ => None,
+        };
+        let (x, y, z) = normalize(mounted);
+        debug!(
+            "Read ({}, {}), normalized to ({:.3}, {:.3})",
+            x_raw, y_raw, x, y
+        );
+
+        Ok(Sample {
+            raw: (x_raw, y_raw),
+            vector: (x, y),
+            z,
+            magnitude,
+            reopened,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mount_matrix_quarter_turn() {
+        let matrix = parse_mount_matrix("0, 1, 0; -1, 0, 0; 0, 0, 1").unwrap();
+        assert_eq!(matrix, [[0.0, 1.0, 0.0], [-1.0, 0.0, 0.0], [0.0, 0.0, 1.0]]);
+
+        // Lying on its side, the chip reads gravity along x while the device
+        // is upright, pointing down along -y
+        let upright = apply_mount_matrix(&matrix, (9.8, 0.0, Some(0.0)));
+        assert_eq!(upright, (0.0, -9.8, Some(0.0)));
+        assert_eq!(normalize(upright), (0.0, -1.0, Some(0.0)));
+        assert_eq!(
+            apply_mount_matrix(&matrix, (0.0, 9.8, Some(0.0))),
+            (9.8, 0.0, Some(0.0))
+        );
+        assert_eq!(
+            apply_mount_matrix(&matrix, (0.0, 9.8, None)),
+            (9.8, 0.0, None)
+        );
+    }
+
+    #[test]
+    fn mount_matrix_invalid() {
+        assert_eq!(parse_mount_matrix(""), None);
+        assert_eq!(parse_mount_matrix("1, 0, 0; 0, 1, 0"), None);
+        assert_eq!(parse_mount_matrix("1, 0; 0, 1; 0, 0"), None);
+        assert_eq!(parse_mount_matrix("1, 0, 0; 0, x, 0; 0, 0, 1"), None);
+    }
+
+    #[test]
+    fn calibration_offsets_and_scales() {
+        let calibration = SensorCalibration {
+            scales: Some([10.0, 20.0, 5.0]),
+            offsets: [1.0, -2.0, 1.0],
+            ..Default::default()
+        };
+        assert_eq!(
+            calibration.apply((3.0, -1.0, Some(7.0))),
+            (4.0, -6.0, Some(4.0))
+        );
+        assert_eq!(calibration.apply((3.0, -1.0, None)), (4.0, -6.0, None));
+    }
+
+    #[test]
+    fn calibration_offsets_without_scale() {
+        let calibration = SensorCalibration {
+            offsets: [-512.0, 512.0, 0.0],
+            ..Default::default()
+        };
+        assert_eq!(
+            calibration.apply((512.0, -1024.0, Some(300.0))),
+            (0.0, -512.0, Some(300.0))
+        );
+    }
+
+    #[test]
+    fn calibration_uniform() {
+        let calibration = SensorCalibration::uniform(10.0, "--scale");
+        assert_eq!(
+            calibration.apply((1.0, 2.0, Some(-3.0))),
+            (1.0, 2.0, Some(-3.0))
+        );
+    }
+
+    #[test]
+    fn normalize_device_readings() {
+        // (device, in_accel_scale, raw reading, normalized x and y)
+        let readings = [
+            // MXC4005, with the panel mounted sideways
+            (
+                "PineTab upright",
+                0.009582,
+                (1018.0, 22.0, -64.0),
+                (0.998, 0.022),
+            ),
+            (
+                "PineTab flat",
+                0.009582,
+                (4.0, -10.0, 1023.0),
+                (0.004, -0.01),
+            ),
+            // HID sensor hub, in mg
+            (
+                "ThinkPad Yoga upright",
+                0.009807,
+                (-12.0, -998.0, 60.0),
+                (-0.012, -0.998),
+            ),
+            (
+                "ThinkPad Yoga tent",
+                0.009807,
+                (8.0, 850.0, -520.0),
+                (0.008, 0.853),
+            ),
+            // 256 per g, with the scale in m/s² and in g
+            (
+                "BMA250 on its side",
+                0.038344,
+                (255.0, -3.0, 10.0),
+                (0.999, -0.012),
+            ),
+            (
+                "BMA250 upside down",
+                0.003906,
+                (-2.0, 256.0, 4.0),
+                (-0.008, 1.0),
+            ),
+            (
+                "BMA250 tilted",
+                0.038344,
+                (-180.0, -181.0, 12.0),
+                (-0.704, -0.708),
+            ),
+        ];
+        for (device, scale, (x, y, z), expected) in readings {
+            let calibration = SensorCalibration::uniform(scale, "sysfs");
+            let (x, y, _) = normalize(calibration.apply((x, y, Some(z))));
+            assert!(
+                (x - expected.0).abs() < 0.001 && (y - expected.1).abs() < 0.001,
+                "{}: ({}, {}) instead of {:?}",
+                device,
+                x,
+                y,
+                expected
+            );
+        }
+    }
+
+    /// A temporary IIO device directory holding `files`
+    fn device(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, contents) in files {
+            fs::write(dir.path().join(name), contents).unwrap();
+        }
+        dir
+    }
+
+    fn options() -> SensorOptions {
+        SensorOptions {
+            kind: "sysfs".to_owned(),
+            device_name: None,
+            accel_name: None,
+            accel_path: None,
+            poll_mode: PollMode::Interval,
+            fallback_scale: None,
+            scale: None,
+            honor_negative_scale: false,
+            sampling_frequency: None,
+            terminate: Arc::default(),
+        }
+    }
+
+    fn read_channel(device: &Path, axis: &str) -> f32 {
+        match read_raw(&accel_channel(device, axis).unwrap()) {
+            Ok(value) => value,
+            Err(RawError::Unreadable(e)) | Err(RawError::Invalid(e)) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn raw_channels() {
+        let dir = device(&[
+            ("in_accel_x_raw", "-512\n"),
+            ("in_accel_y_raw", "12\n"),
+            ("in_accel_z_raw", "1020\n"),
+            ("in_accel_scale", "0.009582\n"),
+        ]);
+        let device = dir.path();
+        assert!(has_accel_channels(device));
+        assert!(!has_input_channels(device));
+        assert_eq!(read_channel(device, "x"), -512.0);
+        assert_eq!(read_channel(device, "y"), 12.0);
+        assert_eq!(read_channel(device, "z"), 1020.0);
+        assert_eq!(
+            SensorCalibration::read(device, &options()).scales,
+            Some([0.009582; 3])
+        );
+    }
+
+    #[test]
+    fn input_channels() {
+        let dir = device(&[
+            ("in_accel_x_input", "0.12\n"),
+            ("in_accel_y_input", "-9.81\n"),
+            ("in_accel_z_input", "0\n"),
+        ]);
+        let device = dir.path();
+        assert!(has_accel_channels(device));
+        assert!(has_input_channels(device));
+        assert_eq!(read_channel(device, "x"), 0.12);
+        assert_eq!(read_channel(device, "y"), -9.81);
+        assert_eq!(read_channel(device, "z"), 0.0);
+    }
+
+    #[test]
+    fn raw_channels_win_over_input() {
+        let dir = device(&[
+            ("in_accel_x_raw", "100\n"),
+            ("in_accel_x_input", "0.98\n"),
+            ("in_accel_y_raw", "0\n"),
+        ]);
+        assert!(!has_input_channels(dir.path()));
+        assert_eq!(read_channel(dir.path(), "x"), 100.0);
+    }
+
+    #[test]
+    fn garbled_reading() {
+        let dir = device(&[("in_accel_x_raw", "\n")]);
+        let path = dir.path().join("in_accel_x_raw");
+        assert!(matches!(read_raw(&path), Err(RawError::Invalid(_))));
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(read_raw(&path), Err(RawError::Unreadable(_))));
+    }
+
+    #[test]
+    fn garbled_readings_stop_on_terminate() {
+        let dir = device(&[("in_accel_x_raw", "\n"), ("in_accel_y_raw", "12\n")]);
+        let options = options();
+        options.terminate.store(true, Ordering::SeqCst);
+        let mut sensor = Sensor::open_iio(dir.path(), &options);
+        assert_eq!(
+            sensor.read(Duration::from_secs(60)).err().as_deref(),
+            Some(TERMINATED)
+        );
+    }
+
+    #[test]
+    fn pathological_scales() {
+        assert_eq!(check_scale(0.0, false), None);
+        assert_eq!(check_scale(-0.0, true), None);
+        assert_eq!(check_scale(f32::NAN, false), None);
+        assert_eq!(check_scale(f32::INFINITY, false), None);
+        assert_eq!(check_scale(f32::NEG_INFINITY, true), None);
+        assert_eq!(check_scale(-0.009582, false), Some(0.009582));
+        assert_eq!(check_scale(-0.009582, true), Some(-0.009582));
+        assert_eq!(check_scale(1e30, false), Some(1e30));
+        assert_eq!(
+            check_scale(f32::MIN_POSITIVE, false),
+            Some(f32::MIN_POSITIVE)
+        );
+    }
+
+    #[test]
+    fn zero_scale_is_read_again() {
+        let dir = device(&[("in_accel_scale", "0.000000\n")]);
+        let calibration = SensorCalibration::read(dir.path(), &options());
+        assert_eq!(calibration.scales, None);
+        assert_eq!(calibration.reread.as_deref(), Some(dir.path()));
+
+        let options = SensorOptions {
+            fallback_scale: Some(0.038344),
+            ..options()
+        };
+        let calibration = SensorCalibration::read(dir.path(), &options);
+        assert_eq!(calibration.scales, Some([0.038344; 3]));
+        assert!(calibration.reread.is_some());
+    }
+
+    #[test]
+    fn negative_scale() {
+        let dir = device(&[("in_accel_scale", "-0.009582\n")]);
+        let calibration = SensorCalibration::read(dir.path(), &options());
+        assert_eq!(calibration.scales, Some([0.009582; 3]));
+
+        let options = SensorOptions {
+            honor_negative_scale: true,
+            ..options()
+        };
+        let calibration = SensorCalibration::read(dir.path(), &options);
+        assert_eq!(calibration.scales, Some([-0.009582; 3]));
+    }
+
+    #[test]
+    fn huge_scale_normalizes() {
+        let calibration = SensorCalibration::uniform(1e30, "sysfs");
+        let (x, y, z) = normalize(calibration.apply((0.0, -1e6, Some(0.0))));
+        assert_eq!((x, y, z), (0.0, -1.0, Some(0.0)));
+    }
+}
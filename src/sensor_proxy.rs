@@ -0,0 +1,99 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use zbus::blocking::{Connection, Proxy};
+
+const DESTINATION: &str = "net.hadess.SensorProxy";
+const PATH: &str = "/net/hadess/SensorProxy";
+const INTERFACE: &str = "net.hadess.SensorProxy";
+
+/// Accelerometer readings from iio-sensor-proxy on the system bus. The proxy
+/// only reports coarse orientations, which are turned back into the gravity
+/// vectors rot8 matches against.
+pub struct SensorProxy {
+    proxy: Proxy<'static>,
+    changes: Receiver<String>,
+    orientation: Option<String>,
+}
+
+fn proxy_error(e: zbus::Error) -> String {
+    format!("iio-sensor-proxy request failed: {}", e)
+}
+
+/// Maps an `AccelerometerOrientation` to the vector of the matching
+/// orientation, or to the origin for `undefined` so no orientation matches.
+fn orientation_vector(orientation: &str) -> (f32, f32) {
+    match orientation {
+        "normal" => (0.0, -1.0),
+        "bottom-up" => (0.0, 1.0),
+        "left-up" => (-1.0, 0.0),
+        "right-up" => (1.0, 0.0),
+        _ => (0.0, 0.0),
+    }
+}
+
+impl SensorProxy {
+    /// Connects to iio-sensor-proxy and claims its accelerometer.
+    pub fn open() -> Result<Self, String> {
+        let connection = Connection::system()
+            .map_err(|e| format!("Unable to connect to the system bus: {}", e))?;
+        let proxy = Proxy::new(&connection, DESTINATION, PATH, INTERFACE).map_err(proxy_error)?;
+
+        let has_accelerometer: bool = proxy
+            .get_property("HasAccelerometer")
+            .map_err(proxy_error)?;
+        if !has_accelerometer {
+            return Err("iio-sensor-proxy has no accelerometer".to_owned());
+        }
+        proxy
+            .call_method("ClaimAccelerometer", &())
+            .map_err(proxy_error)?;
+
+        let (sender, changes) = mpsc::channel();
+        let listener = proxy.clone();
+        thread::spawn(move || {
+            for change in listener.receive_property_changed::<String>("AccelerometerOrientation") {
+                if let Ok(orientation) = change.get() {
+                    if sender.send(orientation).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(SensorProxy {
+            proxy,
+            changes,
+            orientation: None,
+        })
+    }
+
+    /// Returns the current orientation right away the first time, and after
+    /// that waits up to `timeout` for it to change.
+    pub fn read(&mut self, timeout: Duration) -> Result<(f32, f32), String> {
+        let orientation = match self.orientation.take() {
+            None => self
+                .proxy
+                .get_property("AccelerometerOrientation")
+                .map_err(proxy_error)?,
+            Some(current) => match self.changes.recv_timeout(timeout) {
+                Ok(orientation) => orientation,
+                Err(RecvTimeoutError::Timeout) => current,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err("Lost the connection to iio-sensor-proxy".to_owned())
+                }
+            },
+        };
+
+        let vector = orientation_vector(&orientation);
+        self.orientation = Some(orientation);
+        Ok(vector)
+    }
+}
+
+impl Drop for SensorProxy {
+    fn drop(&mut self) {
+        let _ = self.proxy.call_method("ReleaseAccelerometer", &());
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::VecDeque;
+
+/// Averages the last few accelerometer vectors to ride out sensor noise. A
+/// window of 1 passes vectors through unchanged.
+pub struct MovingAverage {
+    samples: VecDeque<(f32, f32)>,
+    window: usize,
+}
+
+impl MovingAverage {
+    pub fn new(window: usize) -> Self {
+        MovingAverage {
+            samples: VecDeque::with_capacity(window),
+            window,
+        }
+    }
+
+    /// Adds a vector and returns the average of the window.
+    pub fn push(&mut self, vector: (f32, f32)) -> (f32, f32) {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(vector);
+
+        let (sum_x, sum_y) = self
+            .samples
+            .iter()
+            .fold((0.0, 0.0), |(sum_x, sum_y), (x, y)| (sum_x + x, sum_y + y));
+        let count = self.samples.len() as f32;
+        (sum_x / count, sum_y / count)
+    }
+
+    /// Forgets the samples so far, e.g. after the sensor was reopened.
+    pub fn reset(&mut self) {
+        self.samples.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_of_one_passes_through() {
+        let mut average = MovingAverage::new(1);
+        assert_eq!(average.push((0.5, -1.0)), (0.5, -1.0));
+        assert_eq!(average.push((-1.0, 0.25)), (-1.0, 0.25));
+    }
+
+    #[test]
+    fn averages_while_filling() {
+        let mut average = MovingAverage::new(3);
+        assert_eq!(average.push((1.0, 0.0)), (1.0, 0.0));
+        assert_eq!(average.push((0.0, 1.0)), (0.5, 0.5));
+        assert_eq!(average.push((-1.0, 2.0)), (0.0, 1.0));
+    }
+
+    #[test]
+    fn evicts_the_oldest() {
+        let mut average = MovingAverage::new(2);
+        average.push((4.0, 4.0));
+        average.push((1.0, -1.0));
+        assert_eq!(average.push((3.0, -3.0)), (2.0, -2.0));
+        assert_eq!(average.push((-3.0, 3.0)), (0.0, 0.0));
+    }
+
+    #[test]
+    fn reset_forgets_samples() {
+        let mut average = MovingAverage::new(3);
+        average.push((1.0, 1.0));
+        average.push((1.0, 1.0));
+        average.reset();
+        assert_eq!(average.push((-1.0, 0.5)), (-1.0, 0.5));
+    }
+}
@@ -0,0 +1,167 @@
+use std::env;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use glob::glob;
+use swayipc::{Connection, Error, Fallible, Node};
+
+/// The IPC connection shared by every request, opened on first use.
+static CONNECTION: Mutex<Option<Connection>> = Mutex::new(None);
+
+pub struct Output {
+    pub name: String,
+    pub transform: String,
+}
+
+pub struct Input {
+    pub identifier: String,
+    pub input_type: String,
+}
+
+fn collect_fullscreen(node: Node, app_ids: &mut Vec<String>) {
+    let Node {
+        fullscreen_mode,
+        visible,
+        app_id,
+        window_properties,
+        nodes,
+        floating_nodes,
+        ..
+    } = node;
+    if fullscreen_mode.unwrap_or(0) != 0 && visible.unwrap_or(false) {
+        // XWayland windows have no app_id, only an X11 class
+        app_ids.push(
+            app_id
+                .or_else(|| window_properties.and_then(|properties| properties.class))
+                .unwrap_or_default(),
+        );
+    }
+    for node in nodes.into_iter().chain(floating_nodes) {
+        collect_fullscreen(node, app_ids);
+    }
+}
+
+/// Sockets to fall back on when `$SWAYSOCK` doesn't answer: every sway
+/// socket in `$XDG_RUNTIME_DIR`, which is where a restarted sway ends up
+/// since our `$SWAYSOCK` still points at the old one.
+fn runtime_sockets() -> Vec<PathBuf> {
+    let mut paths = vec![];
+    if let Some(runtime_dir) = env::var_os("XDG_RUNTIME_DIR") {
+        let pattern = PathBuf::from(runtime_dir).join("sway-ipc.*.sock");
+        if let Ok(sockets) = glob(&pattern.to_string_lossy()) {
+            paths.extend(sockets.flatten());
+        }
+    }
+    paths
+}
+
+fn open() -> Result<Connection, String> {
+    let e = match Connection::new() {
+        Ok(connection) => return Ok(connection),
+        Err(e) => e,
+    };
+    let paths = runtime_sockets();
+    paths
+        .iter()
+        .find_map(|path| UnixStream::connect(path).ok())
+        .map(Connection::from)
+        .ok_or_else(|| {
+            if paths.is_empty() {
+                format!(
+                    "Unable to connect to the sway IPC socket: {}, and there is no sway socket \
+                    in $XDG_RUNTIME_DIR",
+                    e
+                )
+            } else {
+                format!(
+                    "Unable to connect to the sway IPC socket: {} (also tried {})",
+                    e,
+                    paths
+                        .iter()
+                        .map(|path| path.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            }
+        })
+}
+
+/// Whether `e` means the connection itself broke, as opposed to sway
+/// answering with something rot8 can't use. Only then is it worth sending
+/// the request again.
+fn connection_lost(e: &Error) -> bool {
+    matches!(e, Error::Io(_) | Error::InvalidMagic(_))
+}
+
+/// Sends a request over the shared connection, reconnecting once if sway
+/// went away in the meantime.
+fn request<T>(send: impl Fn(&mut Connection) -> Fallible<T>) -> Result<T, String> {
+    let mut connection = CONNECTION.lock().unwrap();
+
+    if let Some(stale) = connection.as_mut() {
+        match send(stale) {
+            Ok(reply) => return Ok(reply),
+            Err(e) if !connection_lost(&e) => {
+                return Err(format!("sway IPC request failed: {}", e))
+            }
+            Err(_) => *connection = None,
+        }
+    }
+
+    let mut fresh = open()?;
+    let reply = send(&mut fresh).map_err(|e| format!("sway IPC request failed: {}", e))?;
+    *connection = Some(fresh);
+    Ok(reply)
+}
+
+/// Opens the shared connection, failing if sway can't be reached.
+pub fn connect() -> Result<(), String> {
+    *CONNECTION.lock().unwrap() = Some(open()?);
+    Ok(())
+}
+
+pub fn get_outputs() -> Result<Vec<Output>, String> {
+    Ok(request(Connection::get_outputs)?
+        .into_iter()
+        .map(|output| Output {
+            name: output.name,
+            transform: output.transform.unwrap_or_default(),
+        })
+        .collect())
+}
+
+pub fn get_inputs() -> Result<Vec<Input>, String> {
+    Ok(request(Connection::get_inputs)?
+        .into_iter()
+        .map(|input| Input {
+            identifier: input.identifier,
+            input_type: input.input_type,
+        })
+        .collect())
+}
+
+/// The app_ids, or X11 classes, of the fullscreen windows on screen.
+pub fn get_fullscreen_apps() -> Result<Vec<String>, String> {
+    let mut app_ids = vec![];
+    collect_fullscreen(request(Connection::get_tree)?, &mut app_ids);
+    Ok(app_ids)
+}
+
+/// Runs sway commands, e.g. `output eDP-1 transform 90`, in a single
+/// request.
+pub fn run_commands(commands: &[String]) -> Result<(), String> {
+    if commands.is_empty() {
+        return Ok(());
+    }
+
+    let outcomes = request(|connection| connection.run_command(commands.join("; ")))?;
+    match outcomes
+        .into_iter()
+        .zip(commands)
+        .find_map(|(outcome, command)| outcome.err().map(|e| (e, command)))
+    {
+        Some((e, command)) => Err(format!("sway command '{}' failed: {}", command, e)),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,28 @@
+use sd_notify::NotifyState;
+
+/// Tells systemd that rot8 is up, for `Type=notify` services. Does nothing
+/// when not started by systemd.
+pub fn notify_ready() {
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        warn!("Unable to notify systemd: {}", e);
+    }
+}
+
+/// Whether systemd expects watchdog pings, i.e. `WatchdogSec=` is set.
+pub fn watchdog_enabled() -> bool {
+    let mut usec = 0;
+    sd_notify::watchdog_enabled(false, &mut usec)
+}
+
+/// Shows `status` in `systemctl status`.
+pub fn notify_status(status: &str) {
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Status(status)]) {
+        debug!("Unable to send the status to systemd: {}", e);
+    }
+}
+
+pub fn ping_watchdog() {
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Watchdog]) {
+        debug!("Unable to ping the systemd watchdog: {}", e);
+    }
+}
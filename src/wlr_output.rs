@@ -0,0 +1,304 @@
+use std::convert::TryFrom;
+
+use wayland_client::protocol::wl_output::Transform;
+use wayland_client::protocol::wl_registry::{self, WlRegistry};
+use wayland_client::{event_created_child, Connection, Dispatch, EventQueue, QueueHandle};
+use wayland_protocols_wlr::output_management::v1::client::zwlr_output_configuration_head_v1::{
+    self, ZwlrOutputConfigurationHeadV1,
+};
+use wayland_protocols_wlr::output_management::v1::client::zwlr_output_configuration_v1::{
+    self, ZwlrOutputConfigurationV1,
+};
+use wayland_protocols_wlr::output_management::v1::client::zwlr_output_head_v1::{
+    self, ZwlrOutputHeadV1,
+};
+use wayland_protocols_wlr::output_management::v1::client::zwlr_output_manager_v1::{
+    self, ZwlrOutputManagerV1,
+};
+use wayland_protocols_wlr::output_management::v1::client::zwlr_output_mode_v1::{
+    self, ZwlrOutputModeV1,
+};
+
+struct Head {
+    proxy: ZwlrOutputHeadV1,
+    name: String,
+    enabled: bool,
+    current_mode: Option<ZwlrOutputModeV1>,
+    position: (i32, i32),
+    transform: u32,
+    scale: f64,
+}
+
+/// How the compositor answered an applied configuration.
+enum Outcome {
+    Succeeded,
+    Failed,
+    /// The output configuration changed before ours could be applied
+    Cancelled,
+}
+
+#[derive(Default)]
+struct State {
+    manager: Option<ZwlrOutputManagerV1>,
+    heads: Vec<Head>,
+    serial: Option<u32>,
+    outcome: Option<Outcome>,
+}
+
+impl State {
+    fn head(&mut self, proxy: &ZwlrOutputHeadV1) -> Option<&mut Head> {
+        self.heads.iter_mut().find(|head| head.proxy == *proxy)
+    }
+}
+
+impl Dispatch<WlRegistry, ()> for State {
+    fn event(
+        state: &mut Self,
+        registry: &WlRegistry,
+        event: wl_registry::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wl_registry::Event::Global {
+            name,
+            interface,
+            version,
+        } = event
+        {
+            if interface == "zwlr_output_manager_v1" {
+                state.manager = Some(registry.bind(name, version.min(4), qh, ()));
+            }
+        }
+    }
+}
+
+impl Dispatch<ZwlrOutputManagerV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrOutputManagerV1,
+        event: zwlr_output_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_output_manager_v1::Event::Head { head } => state.heads.push(Head {
+                proxy: head,
+                name: String::new(),
+                enabled: false,
+                current_mode: None,
+                position: (0, 0),
+                transform: 0,
+                scale: 1.0,
+            }),
+            zwlr_output_manager_v1::Event::Done { serial } => state.serial = Some(serial),
+            _ => {}
+        }
+    }
+
+    event_created_child!(State, ZwlrOutputManagerV1, [
+        zwlr_output_manager_v1::EVT_HEAD_OPCODE => (ZwlrOutputHeadV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrOutputHeadV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwlrOutputHeadV1,
+        event: zwlr_output_head_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let zwlr_output_head_v1::Event::Finished = event {
+            state.heads.retain(|head| head.proxy != *proxy);
+            return;
+        }
+
+        let head = match state.head(proxy) {
+            Some(head) => head,
+            None => return,
+        };
+        match event {
+            zwlr_output_head_v1::Event::Name { name } => head.name = name,
+            zwlr_output_head_v1::Event::Enabled { enabled } => head.enabled = enabled != 0,
+            zwlr_output_head_v1::Event::CurrentMode { mode } => head.current_mode = Some(mode),
+            zwlr_output_head_v1::Event::Position { x, y } => head.position = (x, y),
+            zwlr_output_head_v1::Event::Transform { transform } => {
+                if let Ok(transform) = transform.into_result() {
+                    head.transform = transform.into();
+                }
+            }
+            zwlr_output_head_v1::Event::Scale { scale } => head.scale = scale,
+            _ => {}
+        }
+    }
+
+    event_created_child!(State, ZwlrOutputHeadV1, [
+        zwlr_output_head_v1::EVT_MODE_OPCODE => (ZwlrOutputModeV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrOutputModeV1, ()> for State {
+    fn event(
+        _: &mut Self,
+        _: &ZwlrOutputModeV1,
+        _: zwlr_output_mode_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrOutputConfigurationV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrOutputConfigurationV1,
+        event: zwlr_output_configuration_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        state.outcome = match event {
+            zwlr_output_configuration_v1::Event::Succeeded => Some(Outcome::Succeeded),
+            zwlr_output_configuration_v1::Event::Failed => Some(Outcome::Failed),
+            zwlr_output_configuration_v1::Event::Cancelled => Some(Outcome::Cancelled),
+            _ => return,
+        };
+    }
+}
+
+impl Dispatch<ZwlrOutputConfigurationHeadV1, ()> for State {
+    fn event(
+        _: &mut Self,
+        _: &ZwlrOutputConfigurationHeadV1,
+        _: zwlr_output_configuration_head_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+/// Connects to the compositor and waits for the current output state.
+fn connect() -> Result<(EventQueue<State>, State), String> {
+    let connection = Connection::connect_to_env()
+        .map_err(|e| format!("Unable to connect to the Wayland compositor: {}", e))?;
+    let mut event_queue = connection.new_event_queue();
+    let qh = event_queue.handle();
+    connection.display().get_registry(&qh, ());
+
+    let mut state = State::default();
+    event_queue
+        .roundtrip(&mut state)
+        .map_err(|e| format!("Wayland roundtrip failed: {}", e))?;
+    if state.manager.is_none() {
+        return Err("The compositor does not support wlr-output-management".to_owned());
+    }
+
+    while state.serial.is_none() {
+        event_queue
+            .blocking_dispatch(&mut state)
+            .map_err(|e| format!("Wayland dispatch failed: {}", e))?;
+    }
+
+    Ok((event_queue, state))
+}
+
+fn not_found(display: &str) -> String {
+    format!(
+        "Unable to determine rotation state: display {} not found in wlr-output-management heads",
+        display
+    )
+}
+
+/// Returns the names of the enabled heads.
+pub fn get_displays() -> Result<Vec<String>, String> {
+    let (_, state) = connect()?;
+
+    Ok(state
+        .heads
+        .into_iter()
+        .filter(|head| head.enabled)
+        .map(|head| head.name)
+        .collect())
+}
+
+/// Returns the wl_output transform of `display`.
+pub fn get_transform(display: &str) -> Result<u32, String> {
+    let (_, state) = connect()?;
+
+    state
+        .heads
+        .iter()
+        .find(|head| head.name == display)
+        .map(|head| head.transform)
+        .ok_or_else(|| not_found(display))
+}
+
+fn apply_transform(display: &str, transform: u32) -> Result<Outcome, String> {
+    let (mut event_queue, mut state) = connect()?;
+    let qh = event_queue.handle();
+
+    if !state.heads.iter().any(|head| head.name == display) {
+        return Err(not_found(display));
+    }
+    let transform = Transform::try_from(transform)
+        .map_err(|_| format!("Invalid output transform {}", transform))?;
+
+    let manager = state.manager.as_ref().unwrap();
+    let configuration = manager.create_configuration(state.serial.unwrap(), &qh, ());
+    for head in &state.heads {
+        if !head.enabled {
+            configuration.disable_head(&head.proxy);
+            continue;
+        }
+
+        let configuration_head = configuration.enable_head(&head.proxy, &qh, ());
+        if let Some(mode) = &head.current_mode {
+            configuration_head.set_mode(mode);
+        }
+        configuration_head.set_position(head.position.0, head.position.1);
+        configuration_head.set_scale(head.scale);
+        if head.name == display {
+            configuration_head.set_transform(transform);
+        } else if let Ok(current_transform) = Transform::try_from(head.transform) {
+            configuration_head.set_transform(current_transform);
+        }
+    }
+    configuration.apply();
+
+    while state.outcome.is_none() {
+        event_queue
+            .blocking_dispatch(&mut state)
+            .map_err(|e| format!("Wayland dispatch failed: {}", e))?;
+    }
+    configuration.destroy();
+
+    Ok(state.outcome.unwrap())
+}
+
+/// Sets the wl_output transform of `display`, leaving every other output
+/// property untouched.
+pub fn set_transform(display: &str, transform: u32) -> Result<(), String> {
+    let outcome = match apply_transform(display, transform)? {
+        // Another client changed the outputs in the meantime, try once more
+        // on top of the new state
+        Outcome::Cancelled => apply_transform(display, transform)?,
+        outcome => outcome,
+    };
+
+    match outcome {
+        Outcome::Succeeded => Ok(()),
+        Outcome::Failed => Err(format!(
+            "The compositor rejected rotating display {}",
+            display
+        )),
+        Outcome::Cancelled => Err(format!(
+            "The output configuration kept changing while rotating display {}",
+            display
+        )),
+    }
+}
@@ -0,0 +1,295 @@
+use std::fmt::Display;
+
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::randr::{
+    ConnectionExt as _, GetCrtcInfoReply, GetScreenResourcesCurrentReply, Rotation, SetConfig,
+};
+use x11rb::protocol::xinput::{
+    ConnectionExt as _, Device, XIChangePropertyAux, XIGetPropertyItems,
+};
+use x11rb::protocol::xproto::{AtomEnum, ConnectionExt as _, PropMode, Screen};
+use x11rb::rust_connection::RustConnection;
+
+fn x11_error(e: impl Display) -> String {
+    format!("X11 request failed: {}", e)
+}
+
+fn connect() -> Result<(RustConnection, Screen), String> {
+    let (connection, screen_num) =
+        x11rb::connect(None).map_err(|e| format!("Unable to connect to the X server: {}", e))?;
+    let screen = connection.setup().roots[screen_num].clone();
+    Ok((connection, screen))
+}
+
+/// Checks whether the X server can be reached and is a real one, not the
+/// Xwayland of a Wayland compositor, which can't rotate anything.
+pub fn server_running() -> bool {
+    match connect() {
+        Ok((connection, _)) => !matches!(connection.extension_information("XWAYLAND"), Ok(Some(_))),
+        Err(_) => false,
+    }
+}
+
+/// The CRTC currently driving an output, along with the screen resources it
+/// was looked up in.
+struct OutputCrtc {
+    resources: GetScreenResourcesCurrentReply,
+    crtc: u32,
+    info: GetCrtcInfoReply,
+}
+
+fn find_output_crtc(
+    connection: &RustConnection,
+    screen: &Screen,
+    display: &str,
+) -> Result<OutputCrtc, String> {
+    let resources = connection
+        .randr_get_screen_resources_current(screen.root)
+        .map_err(x11_error)?
+        .reply()
+        .map_err(x11_error)?;
+
+    for output in &resources.outputs {
+        let output_info = connection
+            .randr_get_output_info(*output, resources.config_timestamp)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+        if output_info.name != display.as_bytes() {
+            continue;
+        }
+        if output_info.crtc == 0 {
+            return Err(format!("Display {} is not active in RandR", display));
+        }
+
+        let info = connection
+            .randr_get_crtc_info(output_info.crtc, resources.config_timestamp)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+        return Ok(OutputCrtc {
+            crtc: output_info.crtc,
+            resources,
+            info,
+        });
+    }
+
+    Err(format!("Display {} not found in RandR outputs", display))
+}
+
+/// Returns the names of the RandR outputs driving a CRTC, as xrandr lists
+/// them.
+pub fn get_outputs() -> Result<Vec<String>, String> {
+    let (connection, screen) = connect()?;
+    let resources = connection
+        .randr_get_screen_resources_current(screen.root)
+        .map_err(x11_error)?
+        .reply()
+        .map_err(x11_error)?;
+
+    let mut outputs = vec![];
+    for output in &resources.outputs {
+        let output_info = connection
+            .randr_get_output_info(*output, resources.config_timestamp)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+        if output_info.crtc != 0 {
+            outputs.push(String::from_utf8_lossy(&output_info.name).into_owned());
+        }
+    }
+
+    Ok(outputs)
+}
+
+/// Returns the RandR rotation of `display` by its xrandr name: `normal`,
+/// `left`, `inverted` or `right`.
+pub fn get_rotation(display: &str) -> Result<&'static str, String> {
+    let (connection, screen) = connect()?;
+    let rotation = find_output_crtc(&connection, &screen, display)?
+        .info
+        .rotation;
+
+    Ok(if rotation.contains(Rotation::ROTATE90) {
+        "left"
+    } else if rotation.contains(Rotation::ROTATE180) {
+        "inverted"
+    } else if rotation.contains(Rotation::ROTATE270) {
+        "right"
+    } else {
+        "normal"
+    })
+}
+
+/// Rotates `display` to `rotation`, given by its xrandr name, resizing the
+/// screen to fit the new layout like `xrandr --rotate` does.
+pub fn set_rotation(display: &str, rotation: &str) -> Result<(), String> {
+    let rotation = match rotation {
+        "normal" => Rotation::ROTATE0,
+        "left" => Rotation::ROTATE90,
+        "inverted" => Rotation::ROTATE180,
+        "right" => Rotation::ROTATE270,
+        _ => return Err(format!("Invalid RandR rotation '{}'", rotation)),
+    };
+
+    let (connection, screen) = connect()?;
+    let OutputCrtc {
+        resources,
+        crtc,
+        info,
+    } = find_output_crtc(&connection, &screen, display)?;
+
+    let mode = resources
+        .modes
+        .iter()
+        .find(|mode| mode.id == info.mode)
+        .ok_or_else(|| format!("Current mode of display {} not found", display))?;
+    let (width, height) = if rotation == Rotation::ROTATE90 || rotation == Rotation::ROTATE270 {
+        (mode.height, mode.width)
+    } else {
+        (mode.width, mode.height)
+    };
+
+    // The screen has to cover every CRTC, including the rotated one
+    let mut screen_width = info.x as u16 + width;
+    let mut screen_height = info.y as u16 + height;
+    for other_crtc in resources.crtcs.iter().filter(|other| **other != crtc) {
+        let other_info = connection
+            .randr_get_crtc_info(*other_crtc, resources.config_timestamp)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+        if other_info.mode == 0 {
+            continue;
+        }
+        screen_width = screen_width.max(other_info.x as u16 + other_info.width);
+        screen_height = screen_height.max(other_info.y as u16 + other_info.height);
+    }
+    // Keep the DPI the same
+    let mm_width = u32::from(screen_width) * u32::from(screen.width_in_millimeters)
+        / u32::from(screen.width_in_pixels).max(1);
+    let mm_height = u32::from(screen_height) * u32::from(screen.height_in_millimeters)
+        / u32::from(screen.height_in_pixels).max(1);
+
+    connection.grab_server().map_err(x11_error)?;
+    let result = (|| {
+        // Turn the CRTC off so it never sticks out of the screen while resizing
+        connection
+            .randr_set_crtc_config(
+                crtc,
+                0,
+                resources.config_timestamp,
+                0,
+                0,
+                0,
+                Rotation::ROTATE0,
+                &[],
+            )
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+        connection
+            .randr_set_screen_size(
+                screen.root,
+                screen_width,
+                screen_height,
+                mm_width,
+                mm_height,
+            )
+            .map_err(x11_error)?
+            .check()
+            .map_err(x11_error)?;
+        connection
+            .randr_set_crtc_config(
+                crtc,
+                0,
+                resources.config_timestamp,
+                info.x,
+                info.y,
+                info.mode,
+                rotation,
+                &info.outputs,
+            )
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)
+    })();
+    connection.ungrab_server().map_err(x11_error)?;
+    connection.flush().map_err(x11_error)?;
+
+    match result?.status {
+        SetConfig::SUCCESS => Ok(()),
+        status => Err(format!(
+            "Unable to rotate display {}: RandR returned status {:?}",
+            display, status
+        )),
+    }
+}
+
+fn find_input_device(connection: &RustConnection, name: &str) -> Result<Option<u16>, String> {
+    // XInput2 requests are only allowed after announcing the version we speak
+    connection
+        .xinput_xi_query_version(2, 0)
+        .map_err(x11_error)?
+        .reply()
+        .map_err(x11_error)?;
+    let devices = connection
+        .xinput_xi_query_device(Device::ALL)
+        .map_err(x11_error)?
+        .reply()
+        .map_err(x11_error)?;
+
+    Ok(devices
+        .infos
+        .iter()
+        .find(|device| device.name == name.as_bytes())
+        .map(|device| device.deviceid))
+}
+
+/// Checks whether an input device called `name` is present.
+pub fn input_device_exists(name: &str) -> Result<bool, String> {
+    let (connection, _) = connect()?;
+    Ok(find_input_device(&connection, name)?.is_some())
+}
+
+fn intern_atom(connection: &RustConnection, name: &str) -> Result<u32, String> {
+    Ok(connection
+        .intern_atom(false, name.as_bytes())
+        .map_err(x11_error)?
+        .reply()
+        .map_err(x11_error)?
+        .atom)
+}
+
+/// Sets the "Coordinate Transformation Matrix" property of the input device
+/// called `device`.
+pub fn set_touch_matrix(device: &str, matrix: &[f32; 9]) -> Result<(), String> {
+    let (connection, _) = connect()?;
+    let deviceid = find_input_device(&connection, device)?
+        .ok_or_else(|| format!("Input device {} not found", device))?;
+    let property = intern_atom(&connection, "Coordinate Transformation Matrix")?;
+    let float = intern_atom(&connection, "FLOAT")?;
+
+    let current = connection
+        .xinput_xi_get_property(deviceid, false, property, AtomEnum::ANY.into(), 0, 9)
+        .map_err(x11_error)?
+        .reply()
+        .map_err(x11_error)?;
+    let is_matrix = matches!(current.items, XIGetPropertyItems::Data32(_))
+        && current.type_ == float
+        && current.num_items == 9;
+    if !is_matrix {
+        return Err(format!(
+            "Input device {} has no Coordinate Transformation Matrix",
+            device
+        ));
+    }
+
+    let items = XIChangePropertyAux::Data32(matrix.iter().map(|value| value.to_bits()).collect());
+    let result = connection
+        .xinput_xi_change_property(deviceid, PropMode::REPLACE, property, float, 9, &items)
+        .map_err(x11_error)?
+        .check()
+        .map_err(x11_error);
+    result
+}